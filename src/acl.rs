@@ -0,0 +1,222 @@
+//! Access control policy
+//!
+//! Rules map users and OS groups to the profiles and hosts they may reach and
+//! the actions they may take there. Without any rules everything is allowed;
+//! once a rule exists, anything not granted by some rule is denied.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Profile;
+
+/// Something a user may be allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Open terminals
+    Open,
+    /// Share terminals with other users or by link
+    Share,
+    /// Skip session recording (reserved; nothing is recorded yet)
+    RecordExempt,
+    /// Use the admin API and shadow terminals
+    Admin,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Action::Open => "open",
+            Action::Share => "share",
+            Action::RecordExempt => "record-exempt",
+            Action::Admin => "admin",
+        })
+    }
+}
+
+/// What an action applies to
+#[derive(Debug, Clone, Copy)]
+pub struct Target<'a> {
+    /// Host profile, if any
+    pub profile: Option<&'a str>,
+    /// Host the terminal runs on (`localhost` for local shells)
+    pub host: &'a str,
+}
+
+impl<'a> Target<'a> {
+    /// Target of a terminal opened with a profile (or the default local shell)
+    pub fn terminal(profile: Option<&'a Profile>) -> Self {
+        Self {
+            profile: profile.map(|p| p.name.as_str()),
+            host: profile
+                .and_then(|p| p.host.as_deref())
+                .unwrap_or("localhost"),
+        }
+    }
+}
+
+/// A `[[acl]]` table
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AclRule {
+    /// Usernames, or `*` for everyone
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+    /// OS group names
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// Profile names, or `*` for all
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<String>,
+    /// Host names; `*` matches all and `*.example.com` a domain
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    pub actions: Vec<Action>,
+}
+
+impl AclRule {
+    /// Check if the rule applies to a user
+    fn applies_to(&self, username: &str, groups: &[String]) -> bool {
+        self.users.iter().any(|u| u == "*" || u == username)
+            || self.groups.iter().any(|g| groups.contains(g))
+    }
+
+    /// Check if the rule covers a target (rules without targets cover all)
+    fn covers(&self, target: Option<Target<'_>>) -> bool {
+        if self.profiles.is_empty() && self.hosts.is_empty() {
+            return true;
+        }
+        let Some(target) = target else {
+            return false;
+        };
+        target
+            .profile
+            .is_some_and(|p| self.profiles.iter().any(|rule| rule == "*" || rule == p))
+            || self
+                .hosts
+                .iter()
+                .any(|rule| host_matches(rule, target.host))
+    }
+}
+
+/// Match a host against `*`, `*.domain` or an exact name
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix('*') {
+        Some("") => true,
+        Some(suffix) if suffix.starts_with('.') => host.ends_with(suffix),
+        _ => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+/// Access control policy
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    pub rules: Vec<AclRule>,
+}
+
+impl Acl {
+    /// Check if a policy is configured
+    pub fn enabled(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// Check if a user may take an action (on a target, if it has one)
+    pub fn allows(
+        &self,
+        username: &str,
+        groups: &[String],
+        action: Action,
+        target: Option<Target<'_>>,
+    ) -> bool {
+        !self.enabled()
+            || self.rules.iter().any(|rule| {
+                rule.actions.contains(&action)
+                    && rule.applies_to(username, groups)
+                    && rule.covers(target)
+            })
+    }
+
+    /// Check if any rule applies to a user at all
+    pub fn knows(&self, username: &str, groups: &[String]) -> bool {
+        !self.enabled()
+            || self
+                .rules
+                .iter()
+                .any(|rule| rule.applies_to(username, groups))
+    }
+}
+
+/// OS groups a user belongs to (empty if the user is unknown)
+#[cfg(unix)]
+pub fn user_groups(username: &str) -> Vec<String> {
+    use nix::unistd::{getgrouplist, Group, User};
+    use std::ffi::CString;
+
+    let Ok(Some(user)) = User::from_name(username) else {
+        return Vec::new();
+    };
+    let Ok(name) = CString::new(username) else {
+        return Vec::new();
+    };
+    getgrouplist(&name, user.gid)
+        .unwrap_or_else(|_| vec![user.gid])
+        .into_iter()
+        .filter_map(|gid| Group::from_gid(gid).ok().flatten())
+        .map(|group| group.name)
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn user_groups(_username: &str) -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acl() {
+        let acl = Acl {
+            rules: vec![
+                AclRule {
+                    groups: vec!["ops".to_string()],
+                    hosts: vec!["*.internal".to_string()],
+                    actions: vec![Action::Open, Action::Share],
+                    ..AclRule::default()
+                },
+                AclRule {
+                    users: vec!["alice".to_string()],
+                    profiles: vec!["logs".to_string()],
+                    actions: vec![Action::Open],
+                    ..AclRule::default()
+                },
+                AclRule {
+                    users: vec!["root".to_string()],
+                    actions: vec![Action::Admin],
+                    ..AclRule::default()
+                },
+            ],
+        };
+        let ops = vec!["ops".to_string()];
+        let db = Target {
+            profile: Some("db"),
+            host: "db1.internal",
+        };
+        let logs = Target {
+            profile: Some("logs"),
+            host: "localhost",
+        };
+
+        assert!(acl.allows("bob", &ops, Action::Share, Some(db)));
+        assert!(!acl.allows("bob", &ops, Action::Open, Some(logs)));
+        assert!(!acl.allows("bob", &[], Action::Open, Some(db)));
+        assert!(acl.allows("alice", &[], Action::Open, Some(logs)));
+        assert!(!acl.allows("alice", &[], Action::Share, Some(logs)));
+        assert!(acl.allows("root", &[], Action::Admin, None));
+        assert!(!acl.allows("alice", &[], Action::Admin, None));
+        assert!(acl.knows("bob", &ops));
+        assert!(!acl.knows("mallory", &[]));
+
+        assert!(Acl::default().allows("anyone", &[], Action::Admin, None));
+    }
+}
@@ -0,0 +1,144 @@
+//! Admin API
+//!
+//! Operator endpoints for inspecting and terminating auth sessions and
+//! terminals, and reloading the configuration. Every route requires a
+//! session with the admin role.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use axum_extra::extract::cookie::CookieJar;
+
+use crate::auth::Role;
+use crate::reload;
+use crate::terminal::error::TerminalError;
+use crate::{current_session, AppState};
+
+/// Admin routes, mounted under `/api/admin`
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:id", delete(revoke_session))
+        .route("/terminals", get(list_terminals))
+        .route("/terminals/:id", delete(close_terminal))
+        .route("/reload", post(reload_config))
+}
+
+/// Resolve the admin user behind the request, or reject it
+async fn require_admin(state: &AppState, jar: &CookieJar) -> Result<String, Response> {
+    match current_session(state, jar).await {
+        Some(session) if session.current_role(&state.config.get()) == Role::Admin => {
+            Ok(session.username)
+        }
+        Some(session) => {
+            tracing::warn!("Admin access denied for user: {}", session.username);
+            Err((StatusCode::FORBIDDEN, "Admin access required").into_response())
+        }
+        None => Err((StatusCode::UNAUTHORIZED, "Authentication required").into_response()),
+    }
+}
+
+/// List auth sessions
+async fn list_sessions(State(state): State<AppState>, jar: CookieJar) -> Response {
+    if let Err(response) = require_admin(&state, &jar).await {
+        return response;
+    }
+
+    Json(state.auth_sessions.list_sessions().await).into_response()
+}
+
+/// Revoke an auth session, disconnecting its WebSockets
+async fn revoke_session(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Response {
+    let admin = match require_admin(&state, &jar).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    match state.auth_sessions.revoke_session(&id).await {
+        Some(username) => {
+            tracing::info!(
+                "Admin {} revoked session {} of user {}",
+                admin,
+                id,
+                username
+            );
+            state.audit.record(
+                &admin,
+                "session.revoke",
+                &id,
+                serde_json::json!({ "username": username }),
+            );
+            Json(serde_json::json!({"success": true})).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "Session not found").into_response(),
+    }
+}
+
+/// List terminals
+async fn list_terminals(State(state): State<AppState>, jar: CookieJar) -> Response {
+    if let Err(response) = require_admin(&state, &jar).await {
+        return response;
+    }
+
+    Json(state.session_manager.list_terminals().await).into_response()
+}
+
+/// Force-close a terminal
+async fn close_terminal(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Response {
+    let admin = match require_admin(&state, &jar).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    match state.session_manager.close_terminal(&id).await {
+        Ok(()) => {
+            tracing::info!("Admin {} closed terminal {}", admin, id);
+            state
+                .audit
+                .record(&admin, "terminal.close", &id, serde_json::Value::Null);
+            Json(serde_json::json!({"success": true})).into_response()
+        }
+        Err(TerminalError::NotFound(_)) => {
+            (StatusCode::NOT_FOUND, "Terminal not found").into_response()
+        }
+        Err(e) => {
+            tracing::error!("Admin {} failed to close terminal {}: {}", admin, id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Reload the configuration file without dropping sessions
+async fn reload_config(State(state): State<AppState>, jar: CookieJar) -> Response {
+    let admin = match require_admin(&state, &jar).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    tracing::info!("Admin {} requested a configuration reload", admin);
+    match reload::reload(&state.config, &state.audit, &admin) {
+        Ok(changes) => {
+            Json(serde_json::json!({"success": true, "changes": changes})).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to reload configuration, keeping current: {}", e);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"success": false, "message": e.to_string()})),
+            )
+                .into_response()
+        }
+    }
+}
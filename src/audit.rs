@@ -0,0 +1,81 @@
+//! Audit log
+//!
+//! Records privileged actions. Every event is emitted on the `audit`
+//! tracing target and, if configured, appended to a JSON-lines file.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A single audited action
+#[derive(Debug, Serialize)]
+struct AuditEvent<'a> {
+    timestamp: DateTime<Utc>,
+    actor: &'a str,
+    action: &'a str,
+    target: &'a str,
+    detail: serde_json::Value,
+}
+
+/// Audit log sink
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    file: Option<Arc<Mutex<BufWriter<File>>>>,
+}
+
+impl AuditLog {
+    /// Open the audit log, appending to `path` if given
+    pub fn open(path: Option<&str>) -> std::io::Result<Self> {
+        let file = match path {
+            Some(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Some(Arc::new(Mutex::new(BufWriter::new(file))))
+            }
+            None => None,
+        };
+        Ok(Self { file })
+    }
+
+    /// Record an action by `actor` on `target`
+    pub fn record(&self, actor: &str, action: &str, target: &str, detail: serde_json::Value) {
+        let event = AuditEvent {
+            timestamp: Utc::now(),
+            actor,
+            action,
+            target,
+            detail,
+        };
+
+        tracing::info!(
+            target: "audit",
+            actor = event.actor,
+            action = event.action,
+            object = event.target,
+            detail = %event.detail,
+            "audit event"
+        );
+
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+            let written = serde_json::to_writer(&mut *file, &event)
+                .map_err(std::io::Error::from)
+                .and_then(|_| file.write_all(b"\n"))
+                .and_then(|_| file.flush());
+            if let Err(e) = written {
+                tracing::error!("Failed to write audit log: {}", e);
+            }
+        }
+    }
+
+    /// Flush buffered events and sync the file to disk
+    pub fn flush(&self) {
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(e) = file.flush().and_then(|_| file.get_ref().sync_all()) {
+                tracing::error!("Failed to flush audit log: {}", e);
+            }
+        }
+    }
+}
@@ -4,18 +4,130 @@
 //! - macOS: Uses `dscl . -authonly`
 //! - Linux: Uses `su -c true` or PAM via command
 
+use chrono::{DateTime, Utc};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
 use std::process::Command;
+use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
+use tokio::time::Instant;
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use crate::config::{ClientCertIdentity, Config};
+use crate::tls::PeerCertificate;
+
+/// How long a session stays valid after login
+const SESSION_MAX_AGE: chrono::Duration = chrono::Duration::hours(24);
+
+/// How long a session outlives its last WebSocket, to allow reconnects
+const LOGOUT_GRACE: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// What a session may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Everything a user can, plus the admin API and shadowing
+    Admin,
+    /// Open and share terminals
+    User,
+    /// Only spectate terminals shared with them
+    Viewer,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "user" => Ok(Role::User),
+            "viewer" => Ok(Role::Viewer),
+            _ => Err(format!("expected admin, user or viewer, got {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Admin => "admin",
+            Role::User => "user",
+            Role::Viewer => "viewer",
+        })
+    }
+}
 
 /// Session token with username
 #[derive(Debug, Clone)]
 pub struct Session {
+    /// Public identifier (safe to expose, unlike the token)
+    pub id: String,
+    pub username: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+    pub client_ip: Option<IpAddr>,
+    /// Share link this guest session was opened through
+    pub share_id: Option<String>,
+    /// Hard expiry (guest sessions only)
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Open WebSockets, and when the last one closed
+    sockets: usize,
+    closed_at: Option<Instant>,
+    /// Flipped to true when the session is revoked
+    revoked: Arc<watch::Sender<bool>>,
+}
+
+impl Session {
+    fn new(username: String, role: Role, client_ip: Option<IpAddr>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            username,
+            role,
+            created_at: now,
+            last_activity: now,
+            client_ip,
+            share_id: None,
+            expires_at: None,
+            sockets: 0,
+            closed_at: None,
+            revoked: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// The session's role under the current config
+    ///
+    /// Roles follow config reloads; guests stay viewers.
+    pub fn current_role(&self, config: &Config) -> Role {
+        match self.share_id {
+            Some(_) => self.role,
+            None => config.role(&self.username),
+        }
+    }
+
+    /// Check if the session has passed its hard expiry or maximum age
+    pub fn is_expired(&self) -> bool {
+        let now = Utc::now();
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+            || now.signed_duration_since(self.created_at) >= SESSION_MAX_AGE
+    }
+}
+
+/// Session summary for the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
     pub username: String,
-    pub created_at: std::time::Instant,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+    pub client_ip: Option<IpAddr>,
 }
 
 /// Session store for authenticated users
@@ -30,33 +142,161 @@ impl SessionStore {
     }
 
     /// Create a new session for authenticated user
-    pub async fn create_session(&self, username: String) -> String {
+    pub async fn create_session(
+        &self,
+        username: String,
+        role: Role,
+        client_ip: Option<IpAddr>,
+    ) -> String {
+        self.insert(Session::new(username, role, client_ip)).await
+    }
+
+    /// Create a guest session scoped to a share link
+    ///
+    /// Guests are viewers; the link's scope decides if they may take control.
+    pub async fn create_share_session(
+        &self,
+        username: String,
+        client_ip: Option<IpAddr>,
+        share_id: String,
+        expires_at: DateTime<Utc>,
+    ) -> String {
+        let mut session = Session::new(username, Role::Viewer, client_ip);
+        session.share_id = Some(share_id);
+        session.expires_at = Some(expires_at);
+        self.insert(session).await
+    }
+
+    async fn insert(&self, session: Session) -> String {
         let token = generate_token();
-        let session = Session {
-            username,
-            created_at: std::time::Instant::now(),
-        };
         self.sessions.write().await.insert(token.clone(), session);
         token
     }
 
-    /// Validate a session token and return the username
-    pub async fn validate_session(&self, token: &str) -> Option<String> {
+    /// Validate a session token and return the session
+    ///
+    /// Also records the access as session activity.
+    pub async fn get_session(&self, token: &str) -> Option<Session> {
+        let mut sessions = self.sessions.write().await;
+        if sessions.get(token)?.is_expired() {
+            if let Some(session) = sessions.remove(token) {
+                session.revoked.send_replace(true);
+            }
+            return None;
+        }
+        sessions.get_mut(token).map(|s| {
+            s.last_activity = Utc::now();
+            s.clone()
+        })
+    }
+
+    /// Subscribe to revocation of a session
+    ///
+    /// The receiver sees `true` once the session is revoked or removed.
+    pub async fn watch_revocation(&self, token: &str) -> Option<watch::Receiver<bool>> {
         let sessions = self.sessions.read().await;
-        sessions.get(token).map(|s| s.username.clone())
+        sessions.get(token).map(|s| s.revoked.subscribe())
+    }
+
+    /// Count a WebSocket opened on a session
+    pub async fn socket_opened(&self, token: &str) {
+        if let Some(session) = self.sessions.write().await.get_mut(token) {
+            session.sockets += 1;
+        }
+    }
+
+    /// Count a WebSocket closed on a session
+    ///
+    /// Closing the last one logs the session out, unless another opens
+    /// within [`LOGOUT_GRACE`].
+    pub async fn socket_closed(&self, token: &str) {
+        let mut sessions = self.sessions.write().await;
+        let Some(session) = sessions.get_mut(token) else {
+            return;
+        };
+        session.sockets = session.sockets.saturating_sub(1);
+        if session.sockets > 0 {
+            return;
+        }
+        session.closed_at = Some(Instant::now());
+
+        let store = self.clone();
+        let token = token.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(LOGOUT_GRACE).await;
+            let mut sessions = store.sessions.write().await;
+            // A later close restarts the grace period
+            let idle = sessions.get(&token).is_some_and(|s| {
+                s.sockets == 0 && s.closed_at.is_some_and(|t| t.elapsed() >= LOGOUT_GRACE)
+            });
+            if idle {
+                if let Some(session) = sessions.remove(&token) {
+                    session.revoked.send_replace(true);
+                }
+            }
+        });
     }
 
     /// Remove a session
     pub async fn remove_session(&self, token: &str) {
-        self.sessions.write().await.remove(token);
+        if let Some(session) = self.sessions.write().await.remove(token) {
+            session.revoked.send_replace(true);
+        }
+    }
+
+    /// List all active sessions
+    pub async fn list_sessions(&self) -> Vec<SessionInfo> {
+        let sessions = self.sessions.read().await;
+        let mut list: Vec<SessionInfo> = sessions
+            .values()
+            .map(|s| SessionInfo {
+                id: s.id.clone(),
+                username: s.username.clone(),
+                role: s.role,
+                created_at: s.created_at,
+                last_activity: s.last_activity,
+                client_ip: s.client_ip,
+            })
+            .collect();
+        list.sort_by_key(|s| s.created_at);
+        list
+    }
+
+    /// Revoke a session by its public id
+    /// Returns the username of the revoked session, if found
+    pub async fn revoke_session(&self, id: &str) -> Option<String> {
+        let mut sessions = self.sessions.write().await;
+        let token = sessions
+            .iter()
+            .find(|(_, s)| s.id == id)
+            .map(|(token, _)| token.clone())?;
+        let session = sessions.remove(&token)?;
+        session.revoked.send_replace(true);
+        Some(session.username)
     }
 
-    /// Clean up expired sessions (older than 24 hours)
-    #[allow(dead_code)]
+    /// Remove every guest session of a share link, disconnecting their WebSockets
+    pub async fn remove_share_sessions(&self, share_id: &str) {
+        let mut sessions = self.sessions.write().await;
+        sessions.retain(|_, s| {
+            let keep = s.share_id.as_deref() != Some(share_id);
+            if !keep {
+                s.revoked.send_replace(true);
+            }
+            keep
+        });
+    }
+
+    /// Clean up expired sessions (older than [`SESSION_MAX_AGE`])
     pub async fn cleanup_expired(&self) {
-        let max_age = std::time::Duration::from_secs(24 * 60 * 60);
         let mut sessions = self.sessions.write().await;
-        sessions.retain(|_, s| s.created_at.elapsed() < max_age);
+        sessions.retain(|_, s| {
+            let keep = !s.is_expired();
+            if !keep {
+                s.revoked.send_replace(true);
+            }
+            keep
+        });
     }
 }
 
@@ -76,6 +316,86 @@ fn generate_token() -> String {
     hex::encode(hasher.finalize())
 }
 
+/// Check that a username is safe to pass to system commands
+fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Check that an email address is safe to use as a username
+fn is_valid_email(email: &str) -> bool {
+    email
+        .split_once('@')
+        .is_some_and(|(local, domain)| is_valid_username(local) && is_valid_username(domain))
+}
+
+/// Authenticate user by a client certificate the TLS layer already verified
+///
+/// An `email` identity maps to the full address, or with `email_domain` set
+/// to the local part of addresses in that domain; other domains are rejected.
+/// Returns Ok(username) on success, Err(message) on failure
+pub fn authenticate_client_cert(
+    cert: &PeerCertificate,
+    identity: ClientCertIdentity,
+    email_domain: Option<&str>,
+) -> Result<String, String> {
+    let (_, cert) = X509Certificate::from_der(cert.0.as_ref())
+        .map_err(|e| format!("Invalid client certificate: {}", e))?;
+
+    let sans = || {
+        cert.subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|ext| ext.value.general_names.clone())
+            .unwrap_or_default()
+    };
+    let username = match identity {
+        ClientCertIdentity::CommonName => cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string),
+        ClientCertIdentity::Email => {
+            let email = sans().into_iter().find_map(|name| match name {
+                GeneralName::RFC822Name(email) => Some(email.to_string()),
+                _ => None,
+            });
+            match (email, email_domain) {
+                (Some(email), Some(allowed)) => match email.rsplit_once('@') {
+                    Some((local, domain)) if domain.eq_ignore_ascii_case(allowed) => {
+                        Some(local.to_string())
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Client certificate email {} is not in domain {}",
+                            email, allowed
+                        ))
+                    }
+                },
+                (email, _) => email,
+            }
+        }
+        ClientCertIdentity::Dns => sans().into_iter().find_map(|name| match name {
+            GeneralName::DNSName(dns) => Some(dns.to_string()),
+            _ => None,
+        }),
+    };
+
+    let full_email = identity == ClientCertIdentity::Email && email_domain.is_none();
+    match username {
+        Some(username) if !full_email && is_valid_username(&username) => Ok(username),
+        Some(username) if full_email && is_valid_email(&username) => Ok(username),
+        Some(username) => Err(format!(
+            "Invalid username in client certificate: {}",
+            username
+        )),
+        None => Err(format!("Client certificate has no {:?} identity", identity)),
+    }
+}
+
 /// Authenticate user against OS
 /// Returns Ok(username) on success, Err(message) on failure
 pub fn authenticate_os(username: &str, password: &str) -> Result<String, String> {
@@ -85,10 +405,7 @@ pub fn authenticate_os(username: &str, password: &str) -> Result<String, String>
     }
 
     // Sanitize username (prevent command injection)
-    if !username
-        .chars()
-        .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
-    {
+    if !is_valid_username(username) {
         return Err("Invalid username".to_string());
     }
 
@@ -167,13 +484,146 @@ mod tests {
     #[tokio::test]
     async fn test_session_store() {
         let store = SessionStore::new();
-        let token = store.create_session("testuser".to_string()).await;
+        let token = store
+            .create_session("testuser".to_string(), Role::User, None)
+            .await;
 
-        let username = store.validate_session(&token).await;
+        let username = store.get_session(&token).await.map(|s| s.username);
         assert_eq!(username, Some("testuser".to_string()));
 
         store.remove_session(&token).await;
-        let username = store.validate_session(&token).await;
-        assert_eq!(username, None);
+        assert!(store.get_session(&token).await.is_none());
+    }
+
+    #[test]
+    fn test_current_role() {
+        use crate::config::Settings;
+
+        let admins = |users: &[&str]| {
+            Config::from_settings(Settings {
+                admin_users: Some(users.iter().map(|u| u.to_string()).collect()),
+                ..Settings::default()
+            })
+            .unwrap()
+        };
+        let session = Session::new("alice".to_string(), Role::Admin, None);
+        assert_eq!(session.current_role(&admins(&["alice"])), Role::Admin);
+        // Dropped from admin_users by a reload
+        assert_eq!(session.current_role(&admins(&[])), Role::User);
+
+        let mut guest = Session::new("guest-1".to_string(), Role::Viewer, None);
+        guest.share_id = Some("s1".to_string());
+        assert_eq!(guest.current_role(&admins(&["guest-1"])), Role::Viewer);
+    }
+
+    #[tokio::test]
+    async fn test_session_max_age() {
+        let store = SessionStore::new();
+        let token = store
+            .create_session("testuser".to_string(), Role::User, None)
+            .await;
+        let revoked = store.watch_revocation(&token).await.unwrap();
+
+        store.cleanup_expired().await;
+        assert!(store.get_session(&token).await.is_some());
+
+        store
+            .sessions
+            .write()
+            .await
+            .get_mut(&token)
+            .unwrap()
+            .created_at -= SESSION_MAX_AGE;
+        store.cleanup_expired().await;
+        assert!(*revoked.borrow());
+        assert!(store.get_session(&token).await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_logout_on_last_socket() {
+        let store = SessionStore::new();
+        let token = store
+            .create_session("testuser".to_string(), Role::User, None)
+            .await;
+        let settle = || tokio::time::sleep(LOGOUT_GRACE + std::time::Duration::from_secs(1));
+
+        // Another tab keeps the session
+        store.socket_opened(&token).await;
+        store.socket_opened(&token).await;
+        store.socket_closed(&token).await;
+        settle().await;
+        assert!(store.get_session(&token).await.is_some());
+
+        // So does a reconnect within the grace period
+        store.socket_closed(&token).await;
+        store.socket_opened(&token).await;
+        settle().await;
+        assert!(store.get_session(&token).await.is_some());
+
+        store.socket_closed(&token).await;
+        settle().await;
+        assert!(store.get_session(&token).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_session() {
+        let store = SessionStore::new();
+        let token = store
+            .create_session(
+                "testuser".to_string(),
+                Role::User,
+                Some([10, 0, 0, 1].into()),
+            )
+            .await;
+        let revoked = store.watch_revocation(&token).await.unwrap();
+
+        let sessions = store.list_sessions().await;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].client_ip, Some([10, 0, 0, 1].into()));
+
+        let username = store.revoke_session(&sessions[0].id).await;
+        assert_eq!(username, Some("testuser".to_string()));
+        assert!(*revoked.borrow());
+        assert!(store.get_session(&token).await.is_none());
+        assert_eq!(store.revoke_session(&sessions[0].id).await, None);
+    }
+
+    #[test]
+    fn test_authenticate_client_cert() {
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let mut params =
+            rcgen::CertificateParams::new(vec!["bob.example.com".to_string()]).unwrap();
+        params.subject_alt_names.push(rcgen::SanType::Rfc822Name(
+            "robert@example.com".try_into().unwrap(),
+        ));
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "bob");
+        let cert = PeerCertificate(params.self_signed(&key_pair).unwrap().der().clone());
+
+        let user = |identity| authenticate_client_cert(&cert, identity, None);
+        assert_eq!(user(ClientCertIdentity::CommonName), Ok("bob".to_string()));
+        assert_eq!(
+            user(ClientCertIdentity::Email),
+            Ok("robert@example.com".to_string())
+        );
+        // With a domain configured, only its addresses map to local users
+        let email_user =
+            |domain| authenticate_client_cert(&cert, ClientCertIdentity::Email, domain);
+        assert_eq!(email_user(Some("Example.com")), Ok("robert".to_string()));
+        assert!(email_user(Some("evil.example")).is_err());
+        // Dots are allowed in usernames, so a DNS name maps as-is
+        assert_eq!(
+            user(ClientCertIdentity::Dns),
+            Ok("bob.example.com".to_string())
+        );
+
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "bob; rm -rf /");
+        let cert = PeerCertificate(params.self_signed(&key_pair).unwrap().der().clone());
+        assert!(authenticate_client_cert(&cert, ClientCertIdentity::CommonName, None).is_err());
+        assert!(authenticate_client_cert(&cert, ClientCertIdentity::Email, None).is_err());
     }
 }
@@ -0,0 +1,154 @@
+//! Command-line interface
+//!
+//! Flags form the top configuration layer, above the config file and env.
+//! Secrets are deliberately not accepted as flags since they would show up
+//! in the process list.
+
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::config::Settings;
+
+/// Web-based terminal with OS-native authentication
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// TOML config file
+    #[arg(short, long, env = "WEBSHELL_CONFIG", global = true)]
+    pub config: Option<String>,
+
+    /// Server port
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+
+    /// Listen address: ADDR:PORT, [ADDR]:PORT or unix:PATH (repeatable)
+    #[arg(long, global = true)]
+    pub listen: Vec<String>,
+
+    /// Base workspace directory for terminal sessions
+    #[arg(long, global = true)]
+    pub workspace_dir: Option<String>,
+
+    /// Pre-configured host
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+
+    /// Pre-configured username
+    #[arg(long, global = true)]
+    pub user: Option<String>,
+
+    /// User allowed to access the admin API (repeatable)
+    #[arg(long = "admin-user", global = true)]
+    pub admin_users: Vec<String>,
+
+    /// File to append audit events to
+    #[arg(long, global = true)]
+    pub audit_log: Option<String>,
+
+    /// Directory for generated files
+    #[arg(long, global = true)]
+    pub data_dir: Option<String>,
+
+    /// Run local terminals inside named tmux sessions
+    #[arg(long, global = true)]
+    pub tmux: bool,
+
+    /// Supervisor socket; local terminals survive restarts when set
+    #[arg(long, global = true)]
+    pub supervisor_socket: Option<String>,
+
+    /// Serve HTTPS
+    #[arg(long, global = true)]
+    pub tls: bool,
+
+    /// PEM certificate chain
+    #[arg(long, global = true)]
+    pub tls_cert: Option<String>,
+
+    /// PEM private key
+    #[arg(long, global = true)]
+    pub tls_key: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the server (default)
+    Serve,
+    /// Validate the configuration and print the effective settings
+    CheckConfig,
+    /// Run the terminal supervisor (started automatically when configured)
+    Supervisor,
+    /// Run a command in new namespaces (used for sandboxed terminals)
+    #[command(hide = true)]
+    Sandbox(SandboxArgs),
+    /// Join a cgroup, then run a command (used for resource-limited terminals)
+    #[command(hide = true)]
+    JoinCgroup(JoinCgroupArgs),
+}
+
+/// Arguments of `webshell join-cgroup`
+#[derive(Debug, Args)]
+pub struct JoinCgroupArgs {
+    /// cgroup to join
+    #[arg(long)]
+    pub cgroup: PathBuf,
+
+    /// Command and its arguments
+    #[arg(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+/// Arguments of `webshell sandbox`
+#[derive(Debug, Args)]
+pub struct SandboxArgs {
+    /// Root filesystem to overlay (default: /)
+    #[arg(long)]
+    pub rootfs: Option<PathBuf>,
+
+    /// Mount a private tmpfs here and use it as the home directory
+    #[arg(long)]
+    pub home: Option<PathBuf>,
+
+    /// Keep the host network
+    #[arg(long)]
+    pub network: bool,
+
+    /// User and group id to run the command as
+    #[arg(long)]
+    pub uid: u32,
+
+    /// cgroup to join before starting anything
+    #[arg(long)]
+    pub cgroup: Option<PathBuf>,
+
+    /// Command and its arguments
+    #[arg(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+impl Cli {
+    /// Configuration overrides given as flags
+    pub fn settings(&self) -> Settings {
+        let list = |items: &Vec<String>| (!items.is_empty()).then(|| items.clone());
+
+        Settings {
+            port: self.port,
+            listen: list(&self.listen),
+            workspace_dir: self.workspace_dir.clone(),
+            host: self.host.clone(),
+            user: self.user.clone(),
+            admin_users: list(&self.admin_users),
+            audit_log: self.audit_log.clone(),
+            data_dir: self.data_dir.clone(),
+            supervisor_socket: self.supervisor_socket.clone(),
+            tmux: self.tmux.then_some(true),
+            tls: self.tls.then_some(true),
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            ..Settings::default()
+        }
+    }
+}
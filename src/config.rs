@@ -1,9 +1,49 @@
 //! Configuration management
+//!
+//! Settings are layered: built-in defaults, then an optional TOML file, then
+//! environment variables, then command-line flags. Each layer only overrides
+//! the keys it sets, and the result is validated as a whole.
 
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::env;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+use crate::acl::{self, Acl, AclRule};
+use crate::auth::Role;
+
+/// Placeholder for secrets in printed configuration
+const REDACTED: &str = "<redacted>";
+
+/// Configuration error
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read {0}: {1}")]
+    Read(String, std::io::Error),
+
+    #[error("Invalid config file {0}: {1}")]
+    Parse(String, toml::de::Error),
+
+    #[error("Invalid value for {key}: {message}")]
+    Invalid { key: String, message: String },
+}
+
+impl ConfigError {
+    fn invalid(key: &str, message: impl fmt::Display) -> Self {
+        ConfigError::Invalid {
+            key: key.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
 
 /// Authentication method
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AuthMethod {
     /// Password authentication
     Password(String),
@@ -21,17 +61,173 @@ pub enum AuthMethod {
     None,
 }
 
+/// Where a profile's terminals run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileBackend {
+    /// PTY on this machine
+    Local,
+    /// Shell on a remote host over SSH
+    Ssh,
+}
+
+/// A named host profile terminals can be opened with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub backend: ProfileBackend,
+    /// Remote host (SSH only)
+    pub host: Option<String>,
+    /// Remote port (SSH only)
+    pub port: u16,
+    /// Remote user (SSH only)
+    pub user: Option<String>,
+    /// Remote authentication (SSH only)
+    pub auth: AuthMethod,
+    /// Run this instead of a login shell
+    pub command: Option<String>,
+    /// Working directory
+    pub cwd: Option<String>,
+    /// Extra environment variables
+    pub env: BTreeMap<String, String>,
+}
+
+/// Client certificate field that names the user (mTLS)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientCertIdentity {
+    /// Subject common name
+    CommonName,
+    /// Local part of an email SAN
+    Email,
+    /// First DNS SAN
+    Dns,
+}
+
+impl FromStr for ClientCertIdentity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cn" => Ok(ClientCertIdentity::CommonName),
+            "email" => Ok(ClientCertIdentity::Email),
+            "dns" => Ok(ClientCertIdentity::Dns),
+            _ => Err(format!("expected cn, email or dns, got {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ClientCertIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ClientCertIdentity::CommonName => "cn",
+            ClientCertIdentity::Email => "email",
+            ClientCertIdentity::Dns => "dns",
+        })
+    }
+}
+
+/// What gets its own cgroup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupScope {
+    /// One cgroup per terminal
+    Terminal,
+    /// One cgroup shared by all of a user's terminals
+    User,
+}
+
+impl FromStr for CgroupScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terminal" => Ok(CgroupScope::Terminal),
+            "user" => Ok(CgroupScope::User),
+            _ => Err(format!("expected terminal or user, got {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for CgroupScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CgroupScope::Terminal => "terminal",
+            CgroupScope::User => "user",
+        })
+    }
+}
+
+/// A `[[roles]]` table mapping users and OS groups to a role
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleRule {
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+/// Address the server listens on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listen {
+    /// TCP address; `[::]` listens on IPv4 and IPv6 (dual-stack)
+    Tcp(SocketAddr),
+    /// Unix domain socket
+    Unix {
+        path: PathBuf,
+        /// File mode, e.g. `0o660`
+        mode: Option<u32>,
+        /// Owner as `user` or `user:group`
+        owner: Option<String>,
+    },
+}
+
+impl FromStr for Listen {
+    type Err = String;
+
+    /// Parse `ADDR:PORT`, `[ADDR]:PORT` or `unix:PATH`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("Unix socket path is empty".to_string()),
+            Some(path) => Ok(Listen::Unix {
+                path: PathBuf::from(path),
+                mode: None,
+                owner: None,
+            }),
+            None => s
+                .parse()
+                .map(Listen::Tcp)
+                .map_err(|_| format!("Invalid listen address: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listen::Tcp(addr) => write!(f, "{}", addr),
+            Listen::Unix { path, .. } => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
 /// Application configuration
 #[derive(Debug, Clone)]
 pub struct Config {
-    /// HTTP server port
+    /// HTTP server port (used when `listen` is not set)
     pub port: u16,
+    /// Addresses to listen on (default: `0.0.0.0:<port>`)
+    pub listen: Vec<Listen>,
     /// Base workspace directory for terminal sessions
     pub workspace_dir: String,
     /// Maximum terminals per session
     pub max_terminals: usize,
     /// Terminal idle timeout (seconds)
     pub idle_timeout: u64,
+    /// Seconds clients get to wrap up before shutdown closes their terminals
+    pub shutdown_grace: u64,
+    /// Run local terminals inside named tmux sessions
+    pub tmux: bool,
     /// Pre-configured host (optional)
     pub host: Option<String>,
     /// SSH port for remote connections (default: 22)
@@ -40,85 +236,787 @@ pub struct Config {
     pub user: Option<String>,
     /// Authentication method
     pub auth: AuthMethod,
+    /// Users allowed to access the admin API
+    pub admin_users: Vec<String>,
+    /// Users who may only spectate terminals shared with them
+    pub viewer_users: Vec<String>,
+    /// Role mapping, first match wins
+    pub roles: Vec<RoleRule>,
+    /// Role of users no mapping matches
+    pub default_role: Role,
+    /// Audit log file (JSON lines, optional)
+    pub audit_log: Option<String>,
+    /// Serve HTTPS (self-signed unless a certificate is configured)
+    pub tls: bool,
+    /// TLS certificate chain (PEM)
+    pub tls_cert: Option<String>,
+    /// TLS private key (PEM)
+    pub tls_key: Option<String>,
+    /// Port for the HTTP-to-HTTPS redirect listener (TLS only)
+    pub tls_redirect_port: Option<u16>,
+    /// CA bundle (PEM) to verify client certificates against; enables mTLS
+    pub tls_client_ca: Option<String>,
+    /// Reject TLS connections without a client certificate
+    pub tls_client_required: bool,
+    /// Client certificate field mapped to the username
+    pub tls_client_identity: ClientCertIdentity,
+    /// Only domain accepted for `email` identities, mapped to its local part
+    pub tls_client_email_domain: Option<String>,
+    /// Directory for generated state (e.g. self-signed certificates)
+    pub data_dir: String,
+    /// Supervisor socket; local terminals survive restarts when set
+    pub supervisor_socket: Option<String>,
+    /// Bearer token for `/metrics` (disabled if unset)
+    pub metrics_token: Option<String>,
+    /// Warn when a terminal's processes use more CPU than this (100 = one core)
+    pub usage_cpu_percent: Option<f64>,
+    /// Warn when a terminal's processes use more memory than this (MiB)
+    pub usage_rss_mb: Option<u64>,
+    /// Warn when a terminal runs more processes than this
+    pub usage_processes: Option<u32>,
+    /// cgroup v2 directory local shells are placed under (disabled if unset)
+    pub cgroup_root: Option<String>,
+    /// Whether each terminal or each user gets a cgroup
+    pub cgroup_scope: CgroupScope,
+    /// CPU limit per cgroup (cores)
+    pub cgroup_cpu: Option<f64>,
+    /// Memory limit per cgroup (MiB)
+    pub cgroup_memory_mb: Option<u64>,
+    /// Process limit per cgroup
+    pub cgroup_pids: Option<u32>,
+    /// Run local shells in their own namespaces
+    pub sandbox: bool,
+    /// Root filesystem of sandboxed shells, overlaid with a tmpfs (default: `/`)
+    pub sandbox_rootfs: Option<String>,
+    /// Private tmpfs home directory inside the sandbox
+    pub sandbox_home: Option<String>,
+    /// Keep the host network in the sandbox
+    pub sandbox_network: bool,
+    /// User and group id sandboxed shells run as
+    pub sandbox_uid: u32,
+    /// Named host profiles
+    pub profiles: Vec<Profile>,
+    /// Access control policy (allows everything if empty)
+    pub acl: Acl,
+}
+
+/// One configuration layer; keys left unset fall through to the layer below
+///
+/// Field names are the TOML keys.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub port: Option<u16>,
+    pub listen: Option<Vec<String>>,
+    /// Octal, e.g. `"660"`
+    pub socket_mode: Option<String>,
+    pub socket_owner: Option<String>,
+    pub workspace_dir: Option<String>,
+    pub max_terminals: Option<usize>,
+    pub idle_timeout: Option<u64>,
+    pub shutdown_grace: Option<u64>,
+    pub tmux: Option<bool>,
+    pub host: Option<String>,
+    pub ssh_port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub ssh_key: Option<String>,
+    pub ssh_key_data: Option<String>,
+    pub ssh_passphrase: Option<String>,
+    pub admin_users: Option<Vec<String>>,
+    pub viewer_users: Option<Vec<String>>,
+    pub default_role: Option<String>,
+    pub audit_log: Option<String>,
+    pub data_dir: Option<String>,
+    pub supervisor_socket: Option<String>,
+    pub metrics_token: Option<String>,
+    pub usage_cpu_percent: Option<f64>,
+    pub usage_rss_mb: Option<u64>,
+    pub usage_processes: Option<u32>,
+    pub cgroup_root: Option<String>,
+    /// `terminal` or `user`
+    pub cgroup_scope: Option<String>,
+    pub cgroup_cpu: Option<f64>,
+    pub cgroup_memory_mb: Option<u64>,
+    pub cgroup_pids: Option<u32>,
+    pub sandbox: Option<bool>,
+    pub sandbox_rootfs: Option<String>,
+    pub sandbox_home: Option<String>,
+    pub sandbox_network: Option<bool>,
+    pub sandbox_uid: Option<u32>,
+    pub tls: Option<bool>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_redirect_port: Option<u16>,
+    pub tls_client_ca: Option<String>,
+    pub tls_client_required: Option<bool>,
+    pub tls_client_identity: Option<String>,
+    pub tls_client_email_domain: Option<String>,
+    pub profiles: Option<Vec<ProfileSettings>>,
+    pub acl: Option<Vec<AclRule>>,
+    pub roles: Option<Vec<RoleRule>>,
+}
+
+/// A `[[profiles]]` table
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileSettings {
+    pub name: String,
+    /// Defaults to `ssh` if a host is set, else `local`
+    pub backend: Option<ProfileBackend>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub ssh_key: Option<String>,
+    pub ssh_key_data: Option<String>,
+    pub ssh_passphrase: Option<String>,
+    pub command: Option<String>,
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl ProfileSettings {
+    /// Validate a profile table
+    fn into_profile(self) -> Result<Profile, ConfigError> {
+        let key = |field: &str| format!("profiles.{}.{}", self.name, field);
+        if self.name.is_empty() {
+            return Err(ConfigError::invalid("profiles.name", "must not be empty"));
+        }
+
+        let backend = self.backend.unwrap_or(if self.host.is_some() {
+            ProfileBackend::Ssh
+        } else {
+            ProfileBackend::Local
+        });
+        let auth = auth_method(
+            self.password,
+            self.ssh_key,
+            self.ssh_key_data,
+            self.ssh_passphrase,
+        );
+
+        match backend {
+            ProfileBackend::Ssh => {
+                for (field, set) in [("host", self.host.is_some()), ("user", self.user.is_some())] {
+                    if !set {
+                        return Err(ConfigError::invalid(
+                            &key(field),
+                            "required for ssh profiles",
+                        ));
+                    }
+                }
+                if matches!(auth, AuthMethod::None) {
+                    return Err(ConfigError::invalid(
+                        &key("auth"),
+                        "ssh profiles need a password, ssh_key or ssh_key_data",
+                    ));
+                }
+            }
+            ProfileBackend::Local => {
+                for (field, set) in [
+                    ("host", self.host.is_some()),
+                    ("port", self.port.is_some()),
+                    ("user", self.user.is_some()),
+                    ("auth", !matches!(auth, AuthMethod::None)),
+                ] {
+                    if set {
+                        return Err(ConfigError::invalid(
+                            &key(field),
+                            "only valid for ssh profiles",
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(Profile {
+            name: self.name,
+            backend,
+            host: self.host,
+            port: self.port.unwrap_or(22),
+            user: self.user,
+            auth,
+            command: self.command,
+            cwd: self.cwd,
+            env: self.env,
+        })
+    }
+}
+
+impl Profile {
+    /// Profile table with secrets redacted, for display
+    fn redacted(&self) -> ProfileSettings {
+        let (password, ssh_key, ssh_key_data, ssh_passphrase) = self.auth.redacted();
+        ProfileSettings {
+            name: self.name.clone(),
+            backend: Some(self.backend),
+            host: self.host.clone(),
+            port: (self.backend == ProfileBackend::Ssh).then_some(self.port),
+            user: self.user.clone(),
+            password,
+            ssh_key,
+            ssh_key_data,
+            ssh_passphrase,
+            command: self.command.clone(),
+            cwd: self.cwd.clone(),
+            env: self.env.clone(),
+        }
+    }
+}
+
+impl AuthMethod {
+    /// Settings keys for this method, secrets redacted:
+    /// `(password, ssh_key, ssh_key_data, ssh_passphrase)`
+    #[allow(clippy::type_complexity)]
+    fn redacted(
+        &self,
+    ) -> (
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    ) {
+        let redacted = || Some(REDACTED.to_string());
+        match self {
+            AuthMethod::Password(_) => (redacted(), None, None, None),
+            AuthMethod::KeyFile { path, passphrase } => (
+                None,
+                Some(path.clone()),
+                None,
+                passphrase.as_ref().and_then(|_| redacted()),
+            ),
+            AuthMethod::KeyData { passphrase, .. } => (
+                None,
+                None,
+                redacted(),
+                passphrase.as_ref().and_then(|_| redacted()),
+            ),
+            AuthMethod::None => (None, None, None, None),
+        }
+    }
+}
+
+/// Pick the auth method from the configured secrets
+///
+/// SSH key data takes precedence over a key file, then a password.
+fn auth_method(
+    password: Option<String>,
+    ssh_key: Option<String>,
+    ssh_key_data: Option<String>,
+    passphrase: Option<String>,
+) -> AuthMethod {
+    if let Some(data) = ssh_key_data {
+        AuthMethod::KeyData { data, passphrase }
+    } else if let Some(path) = ssh_key {
+        AuthMethod::KeyFile { path, passphrase }
+    } else if let Some(password) = password {
+        AuthMethod::Password(password)
+    } else {
+        AuthMethod::None
+    }
+}
+
+/// Copy every field `$over` sets onto `$base`
+macro_rules! overlay {
+    ($base:expr, $over:expr, $($field:ident),* $(,)?) => {
+        $(
+            if $over.$field.is_some() {
+                $base.$field = $over.$field;
+            }
+        )*
+    };
+}
+
+impl Settings {
+    /// Read a TOML config file
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_string(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_string(), e))
+    }
+
+    /// Read environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            port: env_parse("PORT")?,
+            listen: env_list("WEBSHELL_LISTEN"),
+            socket_mode: env_string("WEBSHELL_SOCKET_MODE"),
+            socket_owner: env_string("WEBSHELL_SOCKET_OWNER"),
+            workspace_dir: env_string("WORKSPACE_DIR"),
+            max_terminals: env_parse("MAX_TERMINALS")?,
+            idle_timeout: env_parse("IDLE_TIMEOUT")?,
+            shutdown_grace: env_parse("WEBSHELL_SHUTDOWN_GRACE")?,
+            tmux: env_bool("WEBSHELL_TMUX")?,
+            host: env_string("WEBSHELL_HOST"),
+            ssh_port: env_parse("WEBSHELL_PORT")?,
+            user: env_string("WEBSHELL_USER"),
+            password: env_string("WEBSHELL_PASSWORD"),
+            ssh_key: env_string("WEBSHELL_SSH_KEY"),
+            ssh_key_data: env_string("WEBSHELL_SSH_KEY_DATA"),
+            ssh_passphrase: env_string("WEBSHELL_SSH_PASSPHRASE"),
+            admin_users: env_list("WEBSHELL_ADMIN_USERS"),
+            viewer_users: env_list("WEBSHELL_VIEWER_USERS"),
+            default_role: env_string("WEBSHELL_DEFAULT_ROLE"),
+            audit_log: env_string("WEBSHELL_AUDIT_LOG"),
+            data_dir: env_string("WEBSHELL_DATA_DIR"),
+            supervisor_socket: env_string("WEBSHELL_SUPERVISOR_SOCKET"),
+            metrics_token: env_string("WEBSHELL_METRICS_TOKEN"),
+            usage_cpu_percent: env_parse("WEBSHELL_USAGE_CPU_PERCENT")?,
+            usage_rss_mb: env_parse("WEBSHELL_USAGE_RSS_MB")?,
+            usage_processes: env_parse("WEBSHELL_USAGE_PROCESSES")?,
+            cgroup_root: env_string("WEBSHELL_CGROUP_ROOT"),
+            cgroup_scope: env_string("WEBSHELL_CGROUP_SCOPE"),
+            cgroup_cpu: env_parse("WEBSHELL_CGROUP_CPU")?,
+            cgroup_memory_mb: env_parse("WEBSHELL_CGROUP_MEMORY_MB")?,
+            cgroup_pids: env_parse("WEBSHELL_CGROUP_PIDS")?,
+            sandbox: env_bool("WEBSHELL_SANDBOX")?,
+            sandbox_rootfs: env_string("WEBSHELL_SANDBOX_ROOTFS"),
+            sandbox_home: env_string("WEBSHELL_SANDBOX_HOME"),
+            sandbox_network: env_bool("WEBSHELL_SANDBOX_NETWORK")?,
+            sandbox_uid: env_parse("WEBSHELL_SANDBOX_UID")?,
+            tls: env_bool("WEBSHELL_TLS")?,
+            tls_cert: env_string("WEBSHELL_TLS_CERT"),
+            tls_key: env_string("WEBSHELL_TLS_KEY"),
+            tls_redirect_port: env_parse("WEBSHELL_TLS_REDIRECT_PORT")?,
+            tls_client_ca: env_string("WEBSHELL_TLS_CLIENT_CA"),
+            tls_client_required: env_bool("WEBSHELL_TLS_CLIENT_REQUIRED")?,
+            tls_client_identity: env_string("WEBSHELL_TLS_CLIENT_IDENTITY"),
+            tls_client_email_domain: env_string("WEBSHELL_TLS_CLIENT_EMAIL_DOMAIN"),
+            // Only configurable in the config file
+            profiles: None,
+            acl: None,
+            roles: None,
+        })
+    }
+
+    /// Override this layer with every key `other` sets
+    pub fn merge(&mut self, other: Settings) {
+        overlay!(
+            self,
+            other,
+            port,
+            listen,
+            socket_mode,
+            socket_owner,
+            workspace_dir,
+            max_terminals,
+            idle_timeout,
+            shutdown_grace,
+            tmux,
+            host,
+            ssh_port,
+            user,
+            password,
+            ssh_key,
+            ssh_key_data,
+            ssh_passphrase,
+            admin_users,
+            viewer_users,
+            default_role,
+            audit_log,
+            data_dir,
+            supervisor_socket,
+            metrics_token,
+            usage_cpu_percent,
+            usage_rss_mb,
+            usage_processes,
+            cgroup_root,
+            cgroup_scope,
+            cgroup_cpu,
+            cgroup_memory_mb,
+            cgroup_pids,
+            sandbox,
+            sandbox_rootfs,
+            sandbox_home,
+            sandbox_network,
+            sandbox_uid,
+            tls,
+            tls_cert,
+            tls_key,
+            tls_redirect_port,
+            tls_client_ca,
+            tls_client_required,
+            tls_client_identity,
+            tls_client_email_domain,
+            profiles,
+            acl,
+            roles,
+        );
+    }
+}
+
+/// Non-empty environment variable
+fn env_string(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|s| !s.is_empty())
+}
+
+/// Comma-separated environment variable
+fn env_list(name: &str) -> Option<Vec<String>> {
+    env_string(name).map(|s| {
+        s.split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    })
+}
+
+/// Environment variable parsed as `T`
+fn env_parse<T>(name: &str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    env_string(name)
+        .map(|s| {
+            s.parse()
+                .map_err(|e| ConfigError::invalid(name, format!("{:?}: {}", s, e)))
+        })
+        .transpose()
+}
+
+/// Boolean environment variable (`1`/`true`/`yes` or `0`/`false`/`no`)
+fn env_bool(name: &str) -> Result<Option<bool>, ConfigError> {
+    env_string(name)
+        .map(|s| match s.as_str() {
+            "1" | "true" | "yes" => Ok(true),
+            "0" | "false" | "no" => Ok(false),
+            _ => Err(ConfigError::invalid(
+                name,
+                format!("expected true or false, got {:?}", s),
+            )),
+        })
+        .transpose()
 }
 
 impl Default for Config {
     fn default() -> Self {
-        Self {
-            port: 2222,
-            workspace_dir: env::var("HOME").unwrap_or_else(|_| "/tmp".to_string()),
-            max_terminals: 10,
-            idle_timeout: 3600,
-            host: None,
-            ssh_port: 22,
-            user: None,
-            auth: AuthMethod::None,
-        }
+        Self::from_settings(Settings::default()).expect("default settings are valid")
     }
 }
 
 impl Config {
-    /// Load configuration from environment variables
-    pub fn from_env() -> Self {
-        let default_workspace = env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-
-        // Determine auth method from env vars
-        let passphrase = env::var("WEBSHELL_SSH_PASSPHRASE")
-            .ok()
-            .filter(|s| !s.is_empty());
-
-        let auth = if let Ok(key_data) = env::var("WEBSHELL_SSH_KEY_DATA") {
-            if !key_data.is_empty() {
-                AuthMethod::KeyData {
-                    data: key_data,
-                    passphrase,
+    /// Load configuration: defaults, then `config_file`, then env, then `overrides`
+    pub fn load(config_file: Option<&str>, overrides: Settings) -> Result<Self, ConfigError> {
+        let mut settings = Settings::default();
+        if let Some(path) = config_file {
+            settings.merge(Settings::from_file(path)?);
+        }
+        settings.merge(Settings::from_env()?);
+        settings.merge(overrides);
+        Self::from_settings(settings)
+    }
+
+    /// Apply defaults to merged settings and validate them
+    pub fn from_settings(s: Settings) -> Result<Self, ConfigError> {
+        let port = s.port.unwrap_or(2222);
+
+        let socket_mode = s
+            .socket_mode
+            .map(|mode| match u32::from_str_radix(&mode, 8) {
+                Ok(mode) if mode <= 0o7777 => Ok(mode),
+                _ => Err(ConfigError::invalid(
+                    "socket_mode",
+                    format!("expected an octal file mode, got {:?}", mode),
+                )),
+            })
+            .transpose()?;
+        let mut listen = s
+            .listen
+            .unwrap_or_default()
+            .iter()
+            .map(|l| l.parse().map_err(|e| ConfigError::invalid("listen", e)))
+            .collect::<Result<Vec<Listen>, _>>()?;
+        if listen.is_empty() {
+            listen.push(Listen::Tcp(SocketAddr::from(([0, 0, 0, 0], port))));
+        }
+        // Socket mode and owner apply to every Unix listener
+        for l in &mut listen {
+            if let Listen::Unix { mode, owner, .. } = l {
+                *mode = socket_mode;
+                owner.clone_from(&s.socket_owner);
+            }
+        }
+
+        let auth = auth_method(s.password, s.ssh_key, s.ssh_key_data, s.ssh_passphrase);
+
+        let max_terminals = s.max_terminals.unwrap_or(10);
+        if max_terminals == 0 {
+            return Err(ConfigError::invalid("max_terminals", "must be at least 1"));
+        }
+
+        if s.tls_cert.is_some() != s.tls_key.is_some() {
+            return Err(ConfigError::invalid(
+                if s.tls_cert.is_some() {
+                    "tls_key"
+                } else {
+                    "tls_cert"
+                },
+                "tls_cert and tls_key must be set together",
+            ));
+        }
+        if s.usage_cpu_percent.is_some_and(|max| max <= 0.0) {
+            return Err(ConfigError::invalid(
+                "usage_cpu_percent",
+                "must be greater than 0",
+            ));
+        }
+
+        let cgroup_scope = s
+            .cgroup_scope
+            .map(|scope| {
+                scope
+                    .parse()
+                    .map_err(|e| ConfigError::invalid("cgroup_scope", e))
+            })
+            .transpose()?
+            .unwrap_or(CgroupScope::Terminal);
+        if s.cgroup_cpu.is_some_and(|cpu| cpu <= 0.0) {
+            return Err(ConfigError::invalid("cgroup_cpu", "must be greater than 0"));
+        }
+        if s.cgroup_root.is_none() {
+            for (key, set) in [
+                ("cgroup_cpu", s.cgroup_cpu.is_some()),
+                ("cgroup_memory_mb", s.cgroup_memory_mb.is_some()),
+                ("cgroup_pids", s.cgroup_pids.is_some()),
+            ] {
+                if set {
+                    return Err(ConfigError::invalid(key, "requires cgroup_root"));
                 }
-            } else {
-                AuthMethod::None
             }
-        } else if let Ok(key_path) = env::var("WEBSHELL_SSH_KEY") {
-            if !key_path.is_empty() {
-                AuthMethod::KeyFile {
-                    path: key_path,
-                    passphrase,
+        }
+
+        let sandbox = s.sandbox.unwrap_or(false);
+        if let Some(rootfs) = &s.sandbox_rootfs {
+            // Passed to overlayfs as a mount option
+            if !rootfs.starts_with('/') || rootfs.contains([',', ':']) {
+                return Err(ConfigError::invalid(
+                    "sandbox_rootfs",
+                    "must be an absolute path without ',' or ':'",
+                ));
+            }
+        }
+        if s.sandbox_home
+            .as_ref()
+            .is_some_and(|home| !home.starts_with('/'))
+        {
+            return Err(ConfigError::invalid(
+                "sandbox_home",
+                "must be an absolute path",
+            ));
+        }
+        if sandbox && !cfg!(target_os = "linux") {
+            return Err(ConfigError::invalid(
+                "sandbox",
+                "is only supported on Linux",
+            ));
+        }
+        if sandbox && s.tmux == Some(true) {
+            return Err(ConfigError::invalid(
+                "sandbox",
+                "cannot be combined with tmux",
+            ));
+        }
+
+        let tls = s.tls.unwrap_or(false);
+        let tls_enabled = tls || s.tls_cert.is_some();
+        if !tls_enabled {
+            for (key, set) in [
+                ("tls_redirect_port", s.tls_redirect_port.is_some()),
+                ("tls_client_ca", s.tls_client_ca.is_some()),
+            ] {
+                if set {
+                    return Err(ConfigError::invalid(key, "requires TLS to be enabled"));
                 }
-            } else {
-                AuthMethod::None
             }
-        } else if let Ok(password) = env::var("WEBSHELL_PASSWORD") {
-            if !password.is_empty() {
-                AuthMethod::Password(password)
-            } else {
-                AuthMethod::None
+        }
+        let tls_client_required = s.tls_client_required.unwrap_or(false);
+        if tls_client_required && s.tls_client_ca.is_none() {
+            return Err(ConfigError::invalid(
+                "tls_client_required",
+                "requires tls_client_ca",
+            ));
+        }
+        let tls_client_identity = s
+            .tls_client_identity
+            .map(|i| {
+                i.parse()
+                    .map_err(|e| ConfigError::invalid("tls_client_identity", e))
+            })
+            .transpose()?
+            .unwrap_or(ClientCertIdentity::CommonName);
+        if s.tls_client_email_domain.is_some() && tls_client_identity != ClientCertIdentity::Email {
+            return Err(ConfigError::invalid(
+                "tls_client_email_domain",
+                "requires tls_client_identity = \"email\"",
+            ));
+        }
+
+        let profiles = s
+            .profiles
+            .unwrap_or_default()
+            .into_iter()
+            .map(ProfileSettings::into_profile)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut names = HashSet::new();
+        if let Some(duplicate) = profiles.iter().find(|p| !names.insert(&p.name)) {
+            return Err(ConfigError::invalid(
+                "profiles.name",
+                format!("duplicate profile {:?}", duplicate.name),
+            ));
+        }
+
+        let acl_rules = s.acl.unwrap_or_default();
+        for rule in &acl_rules {
+            if rule.users.is_empty() && rule.groups.is_empty() {
+                return Err(ConfigError::invalid(
+                    "acl",
+                    "every rule needs users or groups",
+                ));
             }
-        } else {
-            AuthMethod::None
-        };
+            if rule.actions.is_empty() {
+                return Err(ConfigError::invalid("acl", "every rule needs actions"));
+            }
+        }
 
-        Self {
-            port: env::var("PORT")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(2222),
-            workspace_dir: env::var("WORKSPACE_DIR").unwrap_or(default_workspace),
-            max_terminals: env::var("MAX_TERMINALS")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(10),
-            idle_timeout: env::var("IDLE_TIMEOUT")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(3600),
-            host: env::var("WEBSHELL_HOST").ok().filter(|s| !s.is_empty()),
-            ssh_port: env::var("WEBSHELL_PORT")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(22),
-            user: env::var("WEBSHELL_USER").ok().filter(|s| !s.is_empty()),
+        let roles = s.roles.unwrap_or_default();
+        if roles
+            .iter()
+            .any(|r| r.users.is_empty() && r.groups.is_empty())
+        {
+            return Err(ConfigError::invalid(
+                "roles",
+                "every rule needs users or groups",
+            ));
+        }
+        let default_role = s
+            .default_role
+            .map(|r| {
+                r.parse()
+                    .map_err(|e| ConfigError::invalid("default_role", e))
+            })
+            .transpose()?
+            .unwrap_or(Role::User);
+
+        Ok(Self {
+            port,
+            listen,
+            workspace_dir: s
+                .workspace_dir
+                .unwrap_or_else(|| env::var("HOME").unwrap_or_else(|_| "/tmp".to_string())),
+            max_terminals,
+            idle_timeout: s.idle_timeout.unwrap_or(3600),
+            shutdown_grace: s.shutdown_grace.unwrap_or(10),
+            tmux: s.tmux.unwrap_or(false),
+            host: s.host,
+            ssh_port: s.ssh_port.unwrap_or(22),
+            user: s.user,
             auth,
+            admin_users: s.admin_users.unwrap_or_default(),
+            viewer_users: s.viewer_users.unwrap_or_default(),
+            roles,
+            default_role,
+            audit_log: s.audit_log,
+            tls,
+            tls_cert: s.tls_cert,
+            tls_key: s.tls_key,
+            tls_redirect_port: s.tls_redirect_port,
+            tls_client_ca: s.tls_client_ca,
+            tls_client_required,
+            tls_client_identity,
+            tls_client_email_domain: s.tls_client_email_domain,
+            data_dir: s.data_dir.unwrap_or_else(default_data_dir),
+            supervisor_socket: s.supervisor_socket,
+            metrics_token: s.metrics_token,
+            usage_cpu_percent: s.usage_cpu_percent,
+            usage_rss_mb: s.usage_rss_mb,
+            usage_processes: s.usage_processes,
+            cgroup_root: s.cgroup_root,
+            cgroup_scope,
+            cgroup_cpu: s.cgroup_cpu,
+            cgroup_memory_mb: s.cgroup_memory_mb,
+            cgroup_pids: s.cgroup_pids,
+            sandbox,
+            sandbox_rootfs: s.sandbox_rootfs,
+            sandbox_home: s.sandbox_home,
+            sandbox_network: s.sandbox_network.unwrap_or(false),
+            sandbox_uid: s.sandbox_uid.unwrap_or(65534),
+            profiles,
+            acl: Acl { rules: acl_rules },
+        })
+    }
+
+    /// Effective settings with secrets redacted, for display
+    pub fn redacted(&self) -> Settings {
+        let (password, ssh_key, ssh_key_data, ssh_passphrase) = self.auth.redacted();
+        let (socket_mode, socket_owner) = self
+            .listen
+            .iter()
+            .find_map(|l| match l {
+                Listen::Unix { mode, owner, .. } => {
+                    Some((mode.map(|m| format!("{:o}", m)), owner.clone()))
+                }
+                Listen::Tcp(_) => None,
+            })
+            .unwrap_or_default();
+
+        Settings {
+            port: Some(self.port),
+            listen: Some(self.listen.iter().map(ToString::to_string).collect()),
+            socket_mode,
+            socket_owner,
+            workspace_dir: Some(self.workspace_dir.clone()),
+            max_terminals: Some(self.max_terminals),
+            idle_timeout: Some(self.idle_timeout),
+            shutdown_grace: Some(self.shutdown_grace),
+            tmux: Some(self.tmux),
+            host: self.host.clone(),
+            ssh_port: Some(self.ssh_port),
+            user: self.user.clone(),
+            password,
+            ssh_key,
+            ssh_key_data,
+            ssh_passphrase,
+            admin_users: Some(self.admin_users.clone()),
+            viewer_users: Some(self.viewer_users.clone()),
+            default_role: Some(self.default_role.to_string()),
+            audit_log: self.audit_log.clone(),
+            data_dir: Some(self.data_dir.clone()),
+            supervisor_socket: self.supervisor_socket.clone(),
+            metrics_token: self.metrics_token.as_ref().map(|_| REDACTED.to_string()),
+            usage_cpu_percent: self.usage_cpu_percent,
+            usage_rss_mb: self.usage_rss_mb,
+            usage_processes: self.usage_processes,
+            cgroup_root: self.cgroup_root.clone(),
+            cgroup_scope: Some(self.cgroup_scope.to_string()),
+            cgroup_cpu: self.cgroup_cpu,
+            cgroup_memory_mb: self.cgroup_memory_mb,
+            cgroup_pids: self.cgroup_pids,
+            sandbox: Some(self.sandbox),
+            sandbox_rootfs: self.sandbox_rootfs.clone(),
+            sandbox_home: self.sandbox_home.clone(),
+            sandbox_network: Some(self.sandbox_network),
+            sandbox_uid: Some(self.sandbox_uid),
+            tls: Some(self.tls),
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            tls_redirect_port: self.tls_redirect_port,
+            tls_client_ca: self.tls_client_ca.clone(),
+            tls_client_required: Some(self.tls_client_required),
+            tls_client_identity: Some(self.tls_client_identity.to_string()),
+            tls_client_email_domain: self.tls_client_email_domain.clone(),
+            profiles: Some(self.profiles.iter().map(Profile::redacted).collect()),
+            acl: Some(self.acl.rules.clone()),
+            roles: Some(self.roles.clone()),
         }
     }
 
+    /// Look up a profile by name
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
     /// Check if this is a local connection
     pub fn is_local(&self) -> bool {
         match &self.host {
@@ -132,6 +1030,54 @@ impl Config {
         self.user.is_some() && !matches!(self.auth, AuthMethod::None)
     }
 
+    /// Check if HTTPS is enabled (explicitly, or by configuring a certificate)
+    pub fn tls_enabled(&self) -> bool {
+        self.tls || self.tls_cert.is_some()
+    }
+
+    /// HTTP-to-HTTPS redirect listeners and the HTTPS port each points to
+    ///
+    /// One per TCP `listen` address, on the same host; Unix sockets get none.
+    pub fn redirect_listen(&self) -> Vec<(SocketAddr, u16)> {
+        let Some(redirect_port) = self.tls_redirect_port.filter(|_| self.tls_enabled()) else {
+            return Vec::new();
+        };
+        let mut redirects: Vec<(SocketAddr, u16)> = Vec::new();
+        for listen in &self.listen {
+            if let Listen::Tcp(addr) = listen {
+                let redirect = SocketAddr::new(addr.ip(), redirect_port);
+                if !redirects.iter().any(|(bound, _)| *bound == redirect) {
+                    redirects.push((redirect, addr.port()));
+                }
+            }
+        }
+        redirects
+    }
+
+    /// Role a user gets at login
+    ///
+    /// Admins are listed in `admin_users` or granted `admin` by the ACL.
+    /// Otherwise `viewer_users`, then the first matching `[[roles]]` rule
+    /// (by username or OS group), then `default_role` decide.
+    pub fn role(&self, username: &str) -> Role {
+        let groups = acl::user_groups(username);
+        let is_admin = self.admin_users.iter().any(|u| u == username)
+            || (self.acl.enabled() && self.acl.allows(username, &groups, acl::Action::Admin, None));
+        if is_admin {
+            return Role::Admin;
+        }
+        if self.viewer_users.iter().any(|u| u == username) {
+            return Role::Viewer;
+        }
+        self.roles
+            .iter()
+            .find(|rule| {
+                rule.users.iter().any(|u| u == "*" || u == username)
+                    || rule.groups.iter().any(|g| groups.contains(g))
+            })
+            .map_or(self.default_role, |rule| rule.role)
+    }
+
     /// Get auth method name for UI
     pub fn auth_method_name(&self) -> &'static str {
         match &self.auth {
@@ -142,3 +1088,268 @@ impl Config {
         }
     }
 }
+
+/// Configuration of the running server, swapped as a whole on reload
+#[derive(Debug, Clone)]
+pub struct SharedConfig {
+    current: Arc<RwLock<Arc<Config>>>,
+    /// Config file the server was started with
+    file: Option<String>,
+    /// Command-line overrides the server was started with
+    overrides: Arc<Settings>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config, file: Option<String>, overrides: Settings) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+            file,
+            overrides: Arc::new(overrides),
+        }
+    }
+
+    /// Snapshot of the current configuration
+    ///
+    /// Hold on to it for the duration of a request so it sees one version.
+    pub fn get(&self) -> Arc<Config> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Load the configuration again from the same sources
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        Config::load(self.file.as_deref(), (*self.overrides).clone())
+    }
+
+    /// Swap in a new configuration, returning the previous one
+    pub fn replace(&self, config: Config) -> Arc<Config> {
+        std::mem::replace(
+            &mut *self.current.write().unwrap_or_else(|e| e.into_inner()),
+            Arc::new(config),
+        )
+    }
+}
+
+/// Default data directory: `$HOME/.local/share/webshell`
+fn default_data_dir() -> String {
+    match env::var("HOME") {
+        Ok(home) => format!("{}/.local/share/webshell", home),
+        Err(_) => "/var/lib/webshell".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_layering() {
+        let mut settings: Settings =
+            toml::from_str("port = 4000\nadmin_users = [\"alice\"]\npassword = \"secret\"")
+                .unwrap();
+        settings.merge(Settings {
+            port: Some(5000),
+            ..Settings::default()
+        });
+
+        let config = Config::from_settings(settings).unwrap();
+        assert_eq!(config.port, 5000);
+        assert_eq!(config.admin_users, vec!["alice".to_string()]);
+        assert_eq!(
+            config.listen,
+            vec![Listen::Tcp(SocketAddr::from(([0, 0, 0, 0], 5000)))]
+        );
+        assert_eq!(config.redacted().password.as_deref(), Some(REDACTED));
+
+        assert!(toml::from_str::<Settings>("prot = 4000").is_err());
+    }
+
+    #[test]
+    fn test_redirect_listen() {
+        let config = |listen: &[&str]| {
+            Config::from_settings(Settings {
+                listen: Some(listen.iter().map(|l| l.to_string()).collect()),
+                tls: Some(true),
+                tls_redirect_port: Some(8080),
+                ..Settings::default()
+            })
+            .unwrap()
+        };
+
+        let listen = config(&["127.0.0.1:8443", "[::1]:9443", "unix:/run/webshell.sock"]);
+        assert_eq!(
+            listen.listen[2],
+            Listen::Unix {
+                path: PathBuf::from("/run/webshell.sock"),
+                mode: None,
+                owner: None,
+            }
+        );
+        assert_eq!(
+            listen.redirect_listen(),
+            vec![
+                ("127.0.0.1:8080".parse().unwrap(), 8443),
+                ("[::1]:8080".parse().unwrap(), 9443),
+            ]
+        );
+        assert!(config(&["unix:/run/webshell.sock"])
+            .redirect_listen()
+            .is_empty());
+        assert!(Config::default().redirect_listen().is_empty());
+    }
+
+    #[test]
+    fn test_settings_validation() {
+        let invalid = |settings: Settings| match Config::from_settings(settings) {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            other => panic!("expected a validation error, got {:?}", other),
+        };
+
+        assert_eq!(
+            invalid(Settings {
+                tls_cert: Some("cert.pem".to_string()),
+                ..Settings::default()
+            }),
+            "tls_key"
+        );
+        assert_eq!(
+            invalid(Settings {
+                tls_client_ca: Some("ca.pem".to_string()),
+                ..Settings::default()
+            }),
+            "tls_client_ca"
+        );
+        assert_eq!(
+            invalid(Settings {
+                tls: Some(true),
+                tls_client_email_domain: Some("example.com".to_string()),
+                ..Settings::default()
+            }),
+            "tls_client_email_domain"
+        );
+        assert_eq!(
+            invalid(Settings {
+                listen: Some(vec!["localhost".to_string()]),
+                ..Settings::default()
+            }),
+            "listen"
+        );
+        assert_eq!(
+            invalid(Settings {
+                socket_mode: Some("999".to_string()),
+                ..Settings::default()
+            }),
+            "socket_mode"
+        );
+        assert_eq!(
+            invalid(Settings {
+                default_role: Some("root".to_string()),
+                ..Settings::default()
+            }),
+            "default_role"
+        );
+        assert_eq!(
+            invalid(Settings {
+                cgroup_memory_mb: Some(512),
+                ..Settings::default()
+            }),
+            "cgroup_memory_mb"
+        );
+        assert_eq!(
+            invalid(Settings {
+                cgroup_root: Some("/sys/fs/cgroup/webshell".to_string()),
+                cgroup_scope: Some("session".to_string()),
+                ..Settings::default()
+            }),
+            "cgroup_scope"
+        );
+        assert_eq!(
+            invalid(Settings {
+                sandbox_rootfs: Some("/srv/root,upperdir=/".to_string()),
+                ..Settings::default()
+            }),
+            "sandbox_rootfs"
+        );
+        assert_eq!(
+            invalid(Settings {
+                sandbox: Some(true),
+                tmux: Some(true),
+                ..Settings::default()
+            }),
+            "sandbox"
+        );
+    }
+
+    #[test]
+    fn test_roles() {
+        let settings: Settings = toml::from_str(
+            r#"
+            admin_users = ["alice"]
+            viewer_users = ["victor"]
+            default_role = "viewer"
+
+            [[roles]]
+            role = "user"
+            users = ["ursula", "victor"]
+            "#,
+        )
+        .unwrap();
+        let config = Config::from_settings(settings).unwrap();
+
+        assert_eq!(config.role("alice"), Role::Admin);
+        assert_eq!(config.role("victor"), Role::Viewer);
+        assert_eq!(config.role("ursula"), Role::User);
+        assert_eq!(config.role("nobody-in-particular"), Role::Viewer);
+        assert_eq!(Config::default().role("ursula"), Role::User);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [[profiles]]
+            name = "db"
+            host = "db1.internal"
+            user = "ops"
+            ssh_key = "/etc/webshell/ops_key"
+            cwd = "/srv"
+
+            [[profiles]]
+            name = "logs"
+            command = "journalctl -f"
+            "#,
+        )
+        .unwrap();
+        let config = Config::from_settings(settings).unwrap();
+
+        let db = config.profile("db").unwrap();
+        assert_eq!(db.backend, ProfileBackend::Ssh);
+        assert_eq!(db.port, 22);
+        assert!(matches!(db.auth, AuthMethod::KeyFile { .. }));
+        assert_eq!(
+            config.profile("logs").unwrap().backend,
+            ProfileBackend::Local
+        );
+
+        let profiles = |toml: &str| {
+            Config::from_settings(toml::from_str(toml).unwrap())
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        };
+        assert!(
+            profiles("[[profiles]]\nname = \"db\"\nhost = \"db1\"\nuser = \"ops\"")
+                .unwrap_err()
+                .contains("profiles.db.auth")
+        );
+        assert!(profiles("[[profiles]]\nname = \"local\"\nuser = \"ops\"")
+            .unwrap_err()
+            .contains("profiles.local.user"));
+        assert!(
+            profiles("[[profiles]]\nname = \"a\"\n[[profiles]]\nname = \"a\"")
+                .unwrap_err()
+                .contains("duplicate")
+        );
+    }
+}
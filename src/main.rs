@@ -6,7 +6,7 @@
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        ConnectInfo, Extension, Query, State,
     },
     http::StatusCode,
     response::IntoResponse,
@@ -16,6 +16,7 @@ use axum::{
 use axum_extra::extract::cookie::{Cookie, CookieJar};
 use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::mpsc;
@@ -27,32 +28,168 @@ use tower_http::{
 };
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod acl;
+mod admin;
+mod audit;
 mod auth;
+mod cli;
 mod config;
+mod metrics;
+mod protocol;
+mod reload;
+mod server;
+mod share;
+mod shutdown;
 mod ssh;
+mod supervisor;
 mod terminal;
+mod tls;
 mod types;
 
-use auth::{authenticate_os, SessionStore};
-use config::{AuthMethod, Config};
+use audit::AuditLog;
+use auth::{authenticate_client_cert, authenticate_os, Role, Session, SessionStore};
+use clap::Parser;
+use cli::{Cli, Command};
+use config::{AuthMethod, Config, ProfileBackend, SharedConfig};
+use share::{ShareLink, ShareScope, ShareStore};
 use ssh::{SshAuth, SshConfig};
-use terminal::SessionManager;
-use types::{ShellOutput, WsMessage};
+use supervisor::SupervisorClient;
+use terminal::error::TerminalError;
+use terminal::{Client, SessionManager};
+use tls::PeerCertificate;
+use types::{Envelope, ErrorCode, RequestError, WsMessage};
 
 #[derive(Clone)]
 struct AppState {
-    config: Arc<Config>,
+    config: SharedConfig,
     session_manager: Arc<SessionManager>,
     auth_sessions: SessionStore,
+    shares: ShareStore,
+    audit: AuditLog,
+    shutdown: shutdown::Shutdown,
 }
 
 const SESSION_COOKIE: &str = "webshell_session";
 
-#[tokio::main]
-async fn main() {
-    // Load environment variables
+/// Guest sessions from share links, kept apart from any login
+const SHARE_COOKIE: &str = "webshell_share";
+
+/// Selects the guest session (`?share=1`) instead of the login
+#[derive(Debug, Deserialize)]
+struct SessionQuery {
+    #[serde(default)]
+    share: bool,
+}
+
+impl SessionQuery {
+    fn cookie(&self) -> &'static str {
+        if self.share {
+            SHARE_COOKIE
+        } else {
+            SESSION_COOKIE
+        }
+    }
+}
+
+/// Build a session cookie (`SESSION_COOKIE` or `SHARE_COOKIE`) for a token
+/// The cookie is marked `Secure` whenever TLS is on.
+fn session_cookie(name: &'static str, token: String, secure: bool) -> Cookie<'static> {
+    Cookie::build((name, token))
+        .path("/")
+        .http_only(true)
+        .secure(secure)
+        .same_site(axum_extra::extract::cookie::SameSite::Strict)
+        .build()
+}
+
+/// Resolve the session behind a request's login cookie
+async fn current_session(state: &AppState, jar: &CookieJar) -> Option<Session> {
+    let cookie = jar.get(SESSION_COOKIE)?;
+    state.auth_sessions.get_session(cookie.value()).await
+}
+
+/// Check if the access control policy lets a user take an action
+///
+/// Denials are logged and written to the audit log.
+fn acl_allows(
+    state: &AppState,
+    username: &str,
+    action: acl::Action,
+    target: Option<acl::Target<'_>>,
+) -> bool {
+    let config = state.config.get();
+    let acl = &config.acl;
+    if !acl.enabled() || acl.allows(username, &acl::user_groups(username), action, target) {
+        return true;
+    }
+    acl_denied(state, username, &action.to_string(), target);
+    false
+}
+
+/// Check if the access control policy grants a user anything at all
+fn acl_knows(state: &AppState, username: &str) -> bool {
+    let config = state.config.get();
+    let acl = &config.acl;
+    if !acl.enabled() || acl.knows(username, &acl::user_groups(username)) {
+        return true;
+    }
+    acl_denied(state, username, "login", None);
+    false
+}
+
+/// Check an action against the policy for an existing terminal's target
+async fn terminal_acl_allows(
+    state: &AppState,
+    username: &str,
+    terminal_id: &str,
+    action: acl::Action,
+) -> bool {
+    let config = state.config.get();
+    let profile = state.session_manager.terminal_profile(terminal_id).await;
+    let profile = profile.as_deref().and_then(|name| config.profile(name));
+    acl_allows(
+        state,
+        username,
+        action,
+        Some(acl::Target::terminal(profile)),
+    )
+}
+
+/// Log and audit an access control denial
+fn acl_denied(state: &AppState, username: &str, action: &str, target: Option<acl::Target<'_>>) {
+    let host = target.map(|t| t.host);
+    let profile = target.and_then(|t| t.profile);
+    tracing::warn!(
+        "Access denied: user {} may not {} (profile: {}, host: {})",
+        username,
+        action,
+        profile.unwrap_or("-"),
+        host.unwrap_or("-")
+    );
+    state.audit.record(
+        username,
+        "acl.deny",
+        profile.or(host).unwrap_or(""),
+        serde_json::json!({ "action": action, "profile": profile, "host": host }),
+    );
+}
+
+fn main() {
+    // Namespaces can only be entered before the runtime starts its threads,
+    // and .env is left out of sandboxed shells
+    match &Cli::parse().command {
+        Some(Command::Sandbox(args)) => terminal::sandbox::run(args),
+        Some(Command::JoinCgroup(args)) => terminal::cgroup::run(args),
+        _ => {}
+    }
+
+    // Load environment variables, then parse again so .env can set the config
     dotenvy::dotenv().ok();
+    serve(Cli::parse());
+}
 
+#[tokio::main]
+async fn serve(cli: Cli) {
     // Initialize tracing
     tracing_subscriber::registry()
         .with(
@@ -63,21 +200,88 @@ async fn main() {
         .init();
 
     // Load configuration
-    let config = Arc::new(Config::from_env());
+    let config = match Config::load(cli.config.as_deref(), cli.settings()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(Command::CheckConfig) = cli.command {
+        match toml::to_string_pretty(&config.redacted()) {
+            Ok(effective) => print!("{}", effective),
+            Err(e) => eprintln!("Failed to print configuration: {}", e),
+        }
+        return;
+    }
+    if let Some(Command::Supervisor) = cli.command {
+        let Some(socket) = config.supervisor_socket.as_deref() else {
+            eprintln!("The supervisor needs supervisor_socket to be set");
+            std::process::exit(1);
+        };
+        if let Err(e) = supervisor::daemon::run(std::path::Path::new(socket)).await {
+            eprintln!("Supervisor failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     tracing::info!("Starting WebShell backend on port {}", config.port);
     tracing::info!("Workspace directory: {}", config.workspace_dir);
 
+    // Shared so it can be swapped on reload
+    let shared_config = SharedConfig::new(config.clone(), cli.config.clone(), cli.settings());
+
+    // Hand local terminals to the supervisor, if configured
+    let supervisor = match config.supervisor_socket.as_deref() {
+        Some(socket) => {
+            let log = std::path::Path::new(&config.data_dir).join("supervisor.log");
+            match SupervisorClient::connect_or_spawn(std::path::Path::new(socket), &log).await {
+                Ok(client) => Some(client),
+                Err(e) => {
+                    eprintln!("Failed to reach supervisor at {}: {}", socket, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => None,
+    };
+
     // Create terminal session manager
-    let session_manager = Arc::new(SessionManager::new(config.clone()));
+    let audit = AuditLog::open(config.audit_log.as_deref()).expect("Failed to open audit log");
+    let session_manager = Arc::new(SessionManager::new(
+        shared_config.clone(),
+        audit.clone(),
+        supervisor.clone(),
+    ));
+    if let Some(supervisor) = &supervisor {
+        match session_manager.restore(supervisor).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("Restored {} terminal(s) from the supervisor", count),
+            Err(e) => tracing::error!("Failed to restore terminals: {}", e),
+        }
+    }
 
-    // Create auth session store
+    // Create auth session store, dropping sessions as they expire
     let auth_sessions = SessionStore::new();
+    let sweep = auth_sessions.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            sweep.cleanup_expired().await;
+        }
+    });
 
     let state = AppState {
-        config: config.clone(),
+        config: shared_config,
         session_manager,
         auth_sessions,
+        shares: ShareStore::new(),
+        audit,
+        shutdown: shutdown::Shutdown::new(),
     };
+    reload::spawn_sighup_handler(state.config.clone(), state.audit.clone());
 
     // Resolve static files path
     let static_dir =
@@ -99,11 +303,14 @@ async fn main() {
     // Build the application router
     let app = Router::new()
         .route("/health", get(health_check))
+        .route("/metrics", get(metrics::handler))
         .route("/api/config", get(config_handler))
         .route("/api/login", post(login_handler))
         .route("/api/logout", post(logout_handler))
         .route("/api/session", get(session_check))
         .route("/ws", get(ws_handler))
+        .nest("/api/admin", admin::routes())
+        .nest("/api/shares", share::routes())
         .fallback_service(
             ServeDir::new(&static_dir)
                 .fallback(ServeDir::new(format!("{}/index.html", static_dir))),
@@ -118,16 +325,111 @@ async fn main() {
                         .allow_headers(Any),
                 ),
         )
-        .with_state(state);
+        .with_state(state.clone());
 
-    // Start the server
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    // Set up TLS, generating a self-signed certificate if none is configured
+    let tls_files = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(Ok((cert.clone(), key.clone()))),
+        _ if config.tls => Some(tls::ensure_self_signed(&config.data_dir)),
+        _ => None,
+    };
+    let tls = tls_files.map(|files| {
+        let acceptor = files.and_then(|(cert, key)| {
+            let client_auth = config
+                .tls_client_ca
+                .as_deref()
+                .map(|ca_path| tls::ClientAuth {
+                    ca_path,
+                    required: config.tls_client_required,
+                });
+            let acceptor = tls::acceptor(&cert, &key, client_auth)?;
+            tracing::info!("🔒 TLS certificate: {}", cert);
+            tracing::info!("🔒 SHA-256 fingerprint: {}", tls::fingerprint(&cert)?);
+            Ok(acceptor)
+        });
+        acceptor.unwrap_or_else(|e| {
+            tracing::error!("Failed to set up TLS: {}", e);
+            std::process::exit(1);
+        })
+    });
+    let scheme = if tls.is_some() { "https" } else { "http" };
 
-    tracing::info!("🚀 WebShell backend listening on http://{}", addr);
+    // Redirect plain HTTP to HTTPS, on the hosts the server listens on
+    let redirects = config.redirect_listen();
+    if config.tls_redirect_port.is_some() && redirects.is_empty() {
+        tracing::warn!("tls_redirect_port needs a TCP listen address, not redirecting");
+    }
+    for (addr, https_port) in redirects {
+        let listener = bind_or_exit(&config::Listen::Tcp(addr));
+        tracing::info!(
+            "↪️  Redirecting http://{} to HTTPS on port {}",
+            addr,
+            https_port
+        );
+        tokio::spawn(server::serve(
+            listener,
+            server::redirect_app(https_port),
+            None,
+        ));
+    }
+
+    // Start the server on every configured listener
+    let mut servers = tokio::task::JoinSet::new();
+    for listen in &config.listen {
+        let listener = bind_or_exit(listen);
+        tracing::info!("🚀 WebShell backend listening on {}://{}", scheme, listen);
+        servers.spawn(server::serve(listener, app.clone(), tls.clone()));
+    }
     tracing::info!("📡 WebSocket endpoint: /ws");
 
-    axum::serve(listener, app).await.unwrap();
+    tokio::select! {
+        _ = servers.join_next() => {}
+        _ = shutdown::signal() => graceful_shutdown(&state).await,
+    }
+}
+
+/// Warn clients, wait out the grace period, then close every terminal
+async fn graceful_shutdown(state: &AppState) {
+    let grace_secs = state.config.get().shutdown_grace;
+    tracing::info!("Shutting down, closing terminals in {}s", grace_secs);
+    state.shutdown.begin(grace_secs);
+    state.audit.record(
+        "system",
+        "server.shutdown",
+        "",
+        serde_json::json!({ "grace_secs": grace_secs }),
+    );
+
+    // Wait for the deadline, unless everyone is done sooner
+    let deadline = tokio::time::sleep(std::time::Duration::from_secs(grace_secs));
+    tokio::pin!(deadline);
+    let mut check = tokio::time::interval(std::time::Duration::from_millis(500));
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            _ = shutdown::signal() => {
+                tracing::warn!("Second signal received, skipping grace period");
+                break;
+            }
+            _ = check.tick() => {
+                if state.session_manager.terminal_count().await == 0 {
+                    break;
+                }
+            }
+        }
+    }
+
+    state.session_manager.close_all().await;
+    state.audit.flush();
+    tracing::info!("Shutdown complete");
+}
+
+/// Bind a listener, exiting with an error if the address is unavailable
+fn bind_or_exit(listen: &config::Listen) -> server::Listener {
+    server::Listener::bind(listen).unwrap_or_else(|e| {
+        tracing::error!("Failed to listen on {}: {}", listen, e);
+        std::process::exit(1);
+    })
 }
 
 /// Health check endpoint
@@ -148,19 +450,59 @@ struct ConfigResponse {
     auto_login: bool,
     /// Is this a local connection?
     is_local: bool,
+    /// User named by the connection's client certificate (mTLS)
+    client_cert_user: Option<String>,
+    /// Host profiles terminals can be opened with
+    profiles: Vec<ProfileInfo>,
+    /// Local terminals run in named tmux sessions
+    tmux: bool,
 }
 
 /// Config handler - returns UI configuration
-async fn config_handler(State(state): State<AppState>) -> Json<ConfigResponse> {
+async fn config_handler(
+    State(state): State<AppState>,
+    client_cert: Option<Extension<PeerCertificate>>,
+) -> Json<ConfigResponse> {
+    let config = state.config.get();
+    let client_cert_user = client_cert.and_then(|Extension(cert)| {
+        authenticate_client_cert(
+            &cert,
+            config.tls_client_identity,
+            config.tls_client_email_domain.as_deref(),
+        )
+        .ok()
+    });
+
     Json(ConfigResponse {
-        host: state.config.host.clone(),
-        user: state.config.user.clone(),
-        auth_method: state.config.auth_method_name().to_string(),
-        auto_login: state.config.auto_login(),
-        is_local: state.config.is_local(),
+        host: config.host.clone(),
+        user: config.user.clone(),
+        auth_method: config.auth_method_name().to_string(),
+        auto_login: config.auto_login() || client_cert_user.is_some(),
+        is_local: config.is_local(),
+        client_cert_user,
+        profiles: config
+            .profiles
+            .iter()
+            .map(|p| ProfileInfo {
+                name: p.name.clone(),
+                backend: p.backend,
+                host: p.host.clone(),
+                user: p.user.clone(),
+            })
+            .collect(),
+        tmux: config.tmux,
     })
 }
 
+/// Host profile summary for the UI picker
+#[derive(Debug, Serialize)]
+struct ProfileInfo {
+    name: String,
+    backend: ProfileBackend,
+    host: Option<String>,
+    user: Option<String>,
+}
+
 /// Login request
 #[derive(Debug, Deserialize)]
 struct LoginRequest {
@@ -175,28 +517,40 @@ struct LoginResponse {
     success: bool,
     message: String,
     username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<Role>,
 }
 
-/// Login handler - authenticates against OS or SSH
+/// Login handler - authenticates by client certificate, OS or SSH
 /// Uses env vars if available, falling back to form values
 async fn login_handler(
     State(state): State<AppState>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    client_cert: Option<Extension<PeerCertificate>>,
     jar: CookieJar,
     Form(login): Form<LoginRequest>,
 ) -> impl IntoResponse {
+    let config = state.config.get();
+
+    if state.shutdown.is_started() {
+        return (
+            jar,
+            Json(LoginResponse {
+                success: false,
+                message: "Server is shutting down".to_string(),
+                username: None,
+                role: None,
+            }),
+        );
+    }
+
     // Use configured values, fall back to form input
-    let host = state
-        .config
+    let host = config
         .host
         .clone()
         .or(login.host)
         .unwrap_or_else(|| "localhost".to_string());
-    let username = state
-        .config
-        .user
-        .clone()
-        .or(login.username)
-        .unwrap_or_default();
+    let username = config.user.clone().or(login.username).unwrap_or_default();
 
     // Determine auth method
     let form_password = login.password.unwrap_or_default();
@@ -209,9 +563,16 @@ async fn login_handler(
         is_local
     );
 
-    let auth_result = if is_local {
+    let auth_result = if let Some(Extension(cert)) = &client_cert {
+        // A verified client certificate replaces the password
+        authenticate_client_cert(
+            cert,
+            config.tls_client_identity,
+            config.tls_client_email_domain.as_deref(),
+        )
+    } else if is_local {
         // For local connections, use OS auth
-        let password = match &state.config.auth {
+        let password = match &config.auth {
             AuthMethod::Password(p) => p.clone(),
             _ => form_password.clone(),
         };
@@ -223,17 +584,9 @@ async fn login_handler(
         }
     } else {
         // For remote connections, use SSH
-        let ssh_auth = match &state.config.auth {
-            AuthMethod::Password(p) => SshAuth::Password(p.clone()),
-            AuthMethod::KeyFile { path, passphrase } => SshAuth::KeyFile {
-                path: path.clone(),
-                passphrase: passphrase.clone(),
-            },
-            AuthMethod::KeyData { data, passphrase } => SshAuth::KeyData {
-                data: data.clone(),
-                passphrase: passphrase.clone(),
-            },
-            AuthMethod::None => {
+        let ssh_auth = match SshAuth::from_method(&config.auth) {
+            Some(ssh_auth) => ssh_auth,
+            None => {
                 // Use form password if no auth method configured
                 if form_password.is_empty() {
                     return (
@@ -242,6 +595,7 @@ async fn login_handler(
                             success: false,
                             message: "Password required".to_string(),
                             username: None,
+                            role: None,
                         }),
                     );
                 }
@@ -255,7 +609,7 @@ async fn login_handler(
             // Test SSH connection
             let ssh_config = SshConfig {
                 host: host.clone(),
-                port: state.config.ssh_port,
+                port: config.ssh_port,
                 user: username.clone(),
                 auth: ssh_auth,
             };
@@ -267,23 +621,39 @@ async fn login_handler(
         }
     };
 
+    // Authenticated users still need the policy to grant them something
+    let auth_result = auth_result.and_then(|username| {
+        let target = acl::Target {
+            profile: None,
+            host: &host,
+        };
+        // Guest names belong to share links
+        let allowed = !username.starts_with(share::GUEST_PREFIX)
+            && acl_knows(&state, &username)
+            && (is_local || acl_allows(&state, &username, acl::Action::Open, Some(target)));
+        if allowed {
+            Ok(username)
+        } else {
+            Err("Access denied".to_string())
+        }
+    });
+
     match auth_result {
         Ok(username) => {
-            let token = state.auth_sessions.create_session(username.clone()).await;
-            tracing::info!("Login successful for user: {}", username);
-
-            let cookie = Cookie::build((SESSION_COOKIE, token))
-                .path("/")
-                .http_only(true)
-                .same_site(axum_extra::extract::cookie::SameSite::Strict)
-                .build();
+            let role = config.role(&username);
+            let token = state
+                .auth_sessions
+                .create_session(username.clone(), role, Some(client_addr.ip()))
+                .await;
+            tracing::info!("Login successful for user: {} (role: {})", username, role);
 
             (
-                jar.add(cookie),
+                jar.add(session_cookie(SESSION_COOKIE, token, config.tls_enabled())),
                 Json(LoginResponse {
                     success: true,
                     message: "Login successful".to_string(),
                     username: Some(username),
+                    role: Some(role),
                 }),
             )
         }
@@ -295,6 +665,7 @@ async fn login_handler(
                     success: false,
                     message: e,
                     username: None,
+                    role: None,
                 }),
             )
         }
@@ -302,14 +673,20 @@ async fn login_handler(
 }
 
 /// Logout handler
-async fn logout_handler(State(state): State<AppState>, jar: CookieJar) -> impl IntoResponse {
-    if let Some(cookie) = jar.get(SESSION_COOKIE) {
+async fn logout_handler(
+    State(state): State<AppState>,
+    Query(query): Query<SessionQuery>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let config = state.config.get();
+    if let Some(cookie) = jar.get(query.cookie()) {
         state.auth_sessions.remove_session(cookie.value()).await;
     }
 
-    let removal = Cookie::build((SESSION_COOKIE, ""))
+    let removal = Cookie::build((query.cookie(), ""))
         .path("/")
         .http_only(true)
+        .secure(config.tls_enabled())
         .build();
 
     (
@@ -319,14 +696,30 @@ async fn logout_handler(State(state): State<AppState>, jar: CookieJar) -> impl I
 }
 
 /// Session check - returns current user if authenticated
-async fn session_check(State(state): State<AppState>, jar: CookieJar) -> impl IntoResponse {
-    if let Some(cookie) = jar.get(SESSION_COOKIE) {
-        if let Some(username) = state.auth_sessions.validate_session(cookie.value()).await {
-            return Json(serde_json::json!({
-                "authenticated": true,
-                "username": username
-            }));
-        }
+async fn session_check(
+    State(state): State<AppState>,
+    Query(query): Query<SessionQuery>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let session = match jar.get(query.cookie()) {
+        Some(cookie) => state.auth_sessions.get_session(cookie.value()).await,
+        None => None,
+    };
+    if let Some(session) = session {
+        // Guests are told which terminal their share link is for
+        let share = match &session.share_id {
+            Some(share_id) => state.shares.get(share_id).await,
+            None => None,
+        };
+        return Json(serde_json::json!({
+            "authenticated": true,
+            "username": session.username,
+            "role": session.current_role(&state.config.get()),
+            "share": share.map(|link| serde_json::json!({
+                "terminal_id": link.terminal_id,
+                "scope": link.scope,
+            })),
+        }));
     }
 
     Json(serde_json::json!({
@@ -338,24 +731,51 @@ async fn session_check(State(state): State<AppState>, jar: CookieJar) -> impl In
 async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
+    Query(query): Query<SessionQuery>,
     jar: CookieJar,
 ) -> impl IntoResponse {
+    if state.shutdown.is_started() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down").into_response();
+    }
+
     // Check authentication
-    let session = if let Some(cookie) = jar.get(SESSION_COOKIE) {
+    let session = if let Some(cookie) = jar.get(query.cookie()) {
         let token = cookie.value().to_string();
         state
             .auth_sessions
-            .validate_session(&token)
+            .get_session(&token)
             .await
-            .map(|user| (token, user))
+            .map(|session| (token, session))
     } else {
         None
     };
 
+    // Guest sessions need their share link to still be live
+    let share = match session.as_ref().and_then(|(_, s)| s.share_id.as_deref()) {
+        Some(share_id) => match state.shares.get(share_id).await {
+            Some(link) => Some(link),
+            None => {
+                tracing::warn!("WebSocket rejected: share link {} revoked", share_id);
+                return (StatusCode::UNAUTHORIZED, "Share link revoked").into_response();
+            }
+        },
+        None => None,
+    };
+
+    // Guests are scoped by their share link rather than the policy
+    if let Some((_, session)) = &session {
+        if share.is_none() && !acl_knows(&state, &session.username) {
+            return (StatusCode::FORBIDDEN, "Access denied").into_response();
+        }
+    }
+
     match session {
-        Some((token, user)) => {
-            tracing::info!("WebSocket connection authenticated for user: {}", user);
-            ws.on_upgrade(move |socket| handle_socket(socket, state, user, token))
+        Some((token, session)) => {
+            tracing::info!(
+                "WebSocket connection authenticated for user: {}",
+                session.username
+            );
+            ws.on_upgrade(move |socket| handle_socket(socket, state, session, token, share))
                 .into_response()
         }
         None => {
@@ -369,11 +789,14 @@ async fn ws_handler(
 async fn handle_socket(
     socket: WebSocket,
     state: AppState,
-    username: String,
+    session: Session,
     session_token: String,
+    share: Option<ShareLink>,
 ) {
+    let username = session.username.clone();
+    let role = session.current_role(&state.config.get());
     let (mut sender, mut receiver) = socket.split();
-    let (tx, mut rx) = mpsc::unbounded_channel::<WsMessage>();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Envelope>();
 
     let connection_id = uuid::Uuid::new_v4().to_string();
     tracing::info!(
@@ -382,6 +805,46 @@ async fn handle_socket(
         username
     );
 
+    // The client greets first; incompatible clients are turned away
+    let supported = server_capabilities(&state, role);
+    let negotiated = match protocol::handshake(&mut receiver, &supported).await {
+        Ok(Some(negotiated)) => negotiated,
+        Ok(None) => return,
+        Err(rejection) => {
+            tracing::warn!(
+                "WebSocket handshake failed for {}: {}",
+                connection_id,
+                rejection.reason
+            );
+            protocol::reject(&mut sender, rejection).await;
+            return;
+        }
+    };
+    tracing::debug!(
+        "Connection {} speaks protocol {} with {:?}",
+        connection_id,
+        negotiated.version,
+        negotiated.capabilities
+    );
+    let config = state.config.get();
+    let hello = WsMessage::ServerHello(types::ServerHello {
+        version: negotiated.version,
+        server: format!("webshell/{}", env!("CARGO_PKG_VERSION")),
+        capabilities: negotiated.capabilities,
+        limits: types::ServerLimits {
+            max_terminals: config.max_terminals,
+            idle_timeout: config.idle_timeout,
+            scrollback_bytes: terminal::scrollback::DEFAULT_CAPACITY,
+        },
+        user: types::ServerUser {
+            username: username.clone(),
+            role,
+            guest: share.is_some(),
+        },
+        connection_id: connection_id.clone(),
+    });
+    let _ = tx.send(hello.into());
+
     // Spawn task to send messages to the WebSocket
     let send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
@@ -393,90 +856,546 @@ async fn handle_socket(
         }
     });
 
+    let mut conn = Connection {
+        client: Client {
+            connection_id: connection_id.clone(),
+            username: username.clone(),
+            tx,
+            // A share link's scope decides for guests, the role for everyone else
+            read_only: match &share {
+                Some(link) => link.scope == ShareScope::ReadOnly,
+                None => role == Role::Viewer,
+            },
+            hidden: false,
+            share_id: share.as_ref().map(|link| link.id.clone()),
+        },
+        role,
+        share,
+        shadowing: HashMap::new(),
+    };
+
+    // Session revocation (admin API or logout) ends the connection
+    let mut revoked = match state.auth_sessions.watch_revocation(&session_token).await {
+        Some(revoked) => revoked,
+        None => {
+            send_task.abort();
+            return;
+        }
+    };
+    state.auth_sessions.socket_opened(&session_token).await;
+
+    // Guest sessions end when their share link expires
+    let expires_in = session.expires_at.map(|expires_at| {
+        (expires_at - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default()
+    });
+    let expiry = async {
+        match expires_in {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(expiry);
+
+    let mut shutdown = state.shutdown.subscribe();
+
     // Handle incoming messages
-    while let Some(Ok(msg)) = receiver.next().await {
+    loop {
+        let msg = tokio::select! {
+            msg = receiver.next() => msg,
+            Ok(()) = shutdown.changed() => {
+                if let Some(grace_secs) = *shutdown.borrow_and_update() {
+                    let notice = WsMessage::ServerShutdown(types::ServerShutdown { grace_secs });
+                    let _ = conn.client.tx.send(notice.into());
+                }
+                continue;
+            }
+            _ = revoked.wait_for(|revoked| *revoked) => {
+                tracing::info!("Session revoked, closing WebSocket: {}", connection_id);
+                break;
+            }
+            _ = &mut expiry => {
+                tracing::info!("Session expired, closing WebSocket: {}", connection_id);
+                break;
+            }
+        };
+
         match msg {
-            Message::Text(text) => {
-                if let Ok(ws_msg) = serde_json::from_str::<WsMessage>(&text) {
-                    handle_message(ws_msg, &state, tx.clone()).await;
+            Some(Ok(Message::Text(text))) => {
+                let (reply, req_id) = match serde_json::from_str::<Envelope>(&text) {
+                    Ok(Envelope { msg, req_id }) => {
+                        conn.refresh_role(&state, &session);
+                        let reply = match handle_message(msg, &state, &mut conn).await {
+                            Ok(Some(reply)) => Some(reply),
+                            Ok(None) => req_id.is_some().then_some(WsMessage::Ack),
+                            Err(error) => Some(WsMessage::TerminalError(error)),
+                        };
+                        (reply, req_id)
+                    }
+                    Err(e) => {
+                        tracing::warn!("Malformed message from {}: {}", connection_id, e);
+                        let error = RequestError::new(None, ErrorCode::BadRequest, e.to_string());
+                        (
+                            Some(WsMessage::TerminalError(error)),
+                            malformed_req_id(&text),
+                        )
+                    }
+                };
+                if let Some(msg) = reply {
+                    let _ = conn.client.tx.send(Envelope { msg, req_id });
                 }
             }
-            Message::Close(_) => {
+            Some(Ok(Message::Close(_))) => {
                 tracing::info!("WebSocket closed: {}", connection_id);
                 break;
             }
-            _ => {}
+            Some(Ok(_)) => {}
+            Some(Err(_)) | None => break,
         }
     }
 
     send_task.abort();
+    state.session_manager.detach_client(&connection_id).await;
+    for (id, notify) in std::mem::take(&mut conn.shadowing) {
+        end_shadow(&state, &conn.client, &id, notify).await;
+    }
 
-    // Logout on disconnect
-    state.auth_sessions.remove_session(&session_token).await;
-    tracing::info!(
-        "WebSocket disconnected, session invalidated: {}",
-        connection_id
+    // Logout on disconnect, once no other tab or reconnect holds the session
+    state.auth_sessions.socket_closed(&session_token).await;
+    tracing::info!("WebSocket disconnected: {}", connection_id);
+}
+
+/// Request id of a message that failed to parse, if it has one
+fn malformed_req_id(text: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    value.get("req_id")?.as_str().map(str::to_string)
+}
+
+/// Optional protocol features this server offers a connection
+fn server_capabilities(state: &AppState, role: Role) -> Vec<&'static str> {
+    let config = state.config.get();
+    let mut capabilities = vec![protocol::capability::ATTACH];
+    if role == Role::Admin {
+        capabilities.push(protocol::capability::SHADOW);
+    }
+    if config.tmux {
+        capabilities.push(protocol::capability::TMUX);
+    }
+    if config.supervisor_socket.is_some() {
+        capabilities.push(protocol::capability::PERSISTENT);
+    }
+    capabilities
+}
+
+/// Per-connection state
+struct Connection {
+    client: Client,
+    role: Role,
+    /// Share link a guest connection was opened through
+    share: Option<ShareLink>,
+    /// Terminals an admin connection is shadowing, and whether users were notified
+    shadowing: HashMap<String, bool>,
+}
+
+impl Connection {
+    /// Pick up role changes from a config reload
+    fn refresh_role(&mut self, state: &AppState, session: &Session) {
+        if self.share.is_none() {
+            self.role = session.current_role(&state.config.get());
+            self.client.read_only = self.role == Role::Viewer;
+        }
+    }
+}
+
+/// Record the end of an admin shadow session
+async fn end_shadow(state: &AppState, client: &Client, terminal_id: &str, notify: bool) {
+    state.audit.record(
+        &client.username,
+        "shadow.end",
+        terminal_id,
+        serde_json::json!({ "connection_id": client.connection_id }),
     );
+    if notify {
+        state
+            .session_manager
+            .notify(
+                terminal_id,
+                &format!("Admin {} stopped viewing this terminal", client.username),
+            )
+            .await;
+    }
+}
+
+/// Reply to a client request: a message, or nothing beyond an `ack`
+type Reply = Result<Option<WsMessage>, RequestError>;
+
+/// Error reply for a failed terminal operation
+fn terminal_error(terminal_id: &str, error: &TerminalError) -> RequestError {
+    RequestError::new(Some(terminal_id), error.code(), error.to_string())
 }
 
 /// Handle a WebSocket message
-async fn handle_message(msg: WsMessage, state: &AppState, tx: mpsc::UnboundedSender<WsMessage>) {
+async fn handle_message(msg: WsMessage, state: &AppState, conn: &mut Connection) -> Reply {
+    let client = &conn.client;
+
+    // Share link guests may only use the terminal they were invited to
+    if let Some(link) = &conn.share {
+        let allowed = match &msg {
+            WsMessage::TerminalAttach(attach) => attach.id == link.terminal_id,
+            WsMessage::TerminalDetach(_)
+            | WsMessage::TerminalInput(_)
+            | WsMessage::TerminalSignal(_)
+            | WsMessage::TerminalResize(_)
+            | WsMessage::TerminalClose(_) => true,
+            _ => false,
+        };
+        if !allowed {
+            tracing::warn!(
+                "Share link guest {} sent a disallowed message",
+                client.username
+            );
+            return Err(RequestError::new(
+                None,
+                ErrorCode::AccessDenied,
+                "Not allowed with a share link",
+            ));
+        }
+    }
+
     match msg {
         WsMessage::TerminalOpen(req) => {
+            if conn.role == Role::Viewer {
+                tracing::warn!("Viewer {} may not open terminals", client.username);
+                return Err(RequestError::new(
+                    Some(&req.id),
+                    ErrorCode::AccessDenied,
+                    "Viewers cannot open terminals",
+                ));
+            }
             tracing::info!("Opening terminal: {}", req.id);
 
-            let tx_clone = tx.clone();
-            let terminal_id = req.id.clone();
-
-            // Create output callback
-            let output_callback = move |output: String| {
-                let _ = tx_clone.send(WsMessage::ShellOutput(ShellOutput {
-                    id: terminal_id.clone(),
-                    output,
-                }));
+            let config = state.config.get();
+            let profile = match req.profile.as_deref() {
+                Some(name) => match config.profile(name) {
+                    Some(profile) => Some(profile),
+                    None => {
+                        tracing::warn!(
+                            "User {} requested unknown profile {}",
+                            client.username,
+                            name
+                        );
+                        return Err(RequestError::new(
+                            Some(&req.id),
+                            ErrorCode::UnknownProfile,
+                            format!("Unknown profile: {}", name),
+                        ));
+                    }
+                },
+                None => None,
             };
 
+            if !acl_allows(
+                state,
+                &client.username,
+                acl::Action::Open,
+                Some(acl::Target::terminal(profile)),
+            ) {
+                return Err(RequestError::new(
+                    Some(&req.id),
+                    ErrorCode::AccessDenied,
+                    "Access denied",
+                ));
+            }
+
             // Create the terminal
             match state
                 .session_manager
-                .create_terminal(&req.id, req.cols, req.rows, Box::new(output_callback))
+                .create_terminal(
+                    &req.id,
+                    client,
+                    req.cols,
+                    req.rows,
+                    profile,
+                    req.session.as_deref(),
+                )
                 .await
             {
-                Ok(_) => {
+                Ok(handle) => {
                     tracing::info!("Terminal created: {}", req.id);
+                    Ok(Some(WsMessage::TerminalOpened(types::TerminalOpened {
+                        id: req.id,
+                        pid: handle.pid,
+                        persistent: handle.persistent,
+                    })))
                 }
                 Err(e) => {
                     tracing::error!("Failed to create terminal {}: {}", req.id, e);
+                    Err(terminal_error(&req.id, &e))
                 }
             }
         }
 
-        WsMessage::TerminalInput(input) => {
-            if let Err(e) = state
+        WsMessage::Hello(_) => {
+            tracing::warn!("Repeated hello from {} ignored", conn.client.connection_id);
+            Err(RequestError::new(
+                None,
+                ErrorCode::BadRequest,
+                "Handshake already done",
+            ))
+        }
+
+        WsMessage::TmuxList => {
+            let sessions = terminal::tmux::list_sessions(&client.username).await;
+            Ok(Some(WsMessage::TmuxSessions(types::TmuxSessions {
+                sessions,
+            })))
+        }
+
+        WsMessage::TerminalList => {
+            let terminals = state.session_manager.terminals_for(&client.username).await;
+            Ok(Some(WsMessage::TerminalTerminals(types::TerminalList {
+                terminals,
+            })))
+        }
+
+        WsMessage::TerminalUpdate(update) => {
+            state
                 .session_manager
-                .write_to_terminal(&input.id, &input.input)
+                .update_terminal(&update.id, &client.username, update.name, update.tags)
                 .await
+                .map_err(|e| {
+                    tracing::warn!("Failed to update terminal {}: {}", update.id, e);
+                    terminal_error(&update.id, &e)
+                })?;
+            Ok(None)
+        }
+
+        WsMessage::TerminalAttach(attach) => {
+            // Share link guests are covered by the owner's share permission
+            if conn.share.is_none()
+                && !terminal_acl_allows(state, &client.username, &attach.id, acl::Action::Open)
+                    .await
             {
-                tracing::error!("Failed to write to terminal {}: {}", input.id, e);
+                return Err(RequestError::new(
+                    Some(&attach.id),
+                    ErrorCode::AccessDenied,
+                    "Access denied",
+                ));
             }
+            state
+                .session_manager
+                .attach_terminal(&attach.id, client)
+                .await
+                .map_err(|e| {
+                    tracing::warn!(
+                        "User {} failed to attach to terminal {}: {}",
+                        client.username,
+                        attach.id,
+                        e
+                    );
+                    terminal_error(&attach.id, &e)
+                })?;
+            Ok(None)
         }
 
-        WsMessage::TerminalResize(resize) => {
-            if let Err(e) = state
+        WsMessage::TerminalShadow(shadow) => {
+            if conn.role != Role::Admin {
+                tracing::warn!("Shadowing denied for non-admin user: {}", client.username);
+                return Err(RequestError::new(
+                    Some(&shadow.id),
+                    ErrorCode::AccessDenied,
+                    "Only admins can shadow terminals",
+                ));
+            }
+
+            let shadow_client = Client {
+                hidden: !shadow.notify,
+                ..client.clone()
+            };
+            let owner = state
                 .session_manager
-                .resize_terminal(&resize.id, resize.cols, resize.rows)
+                .shadow_terminal(&shadow.id, &shadow_client)
                 .await
-            {
-                tracing::error!("Failed to resize terminal {}: {}", resize.id, e);
+                .map_err(|e| {
+                    tracing::warn!("Failed to shadow terminal {}: {}", shadow.id, e);
+                    terminal_error(&shadow.id, &e)
+                })?;
+
+            state.audit.record(
+                &client.username,
+                "shadow.start",
+                &shadow.id,
+                serde_json::json!({
+                    "owner": owner,
+                    "notify": shadow.notify,
+                    "connection_id": client.connection_id,
+                }),
+            );
+            if shadow.notify {
+                state
+                    .session_manager
+                    .notify(
+                        &shadow.id,
+                        &format!("Admin {} is viewing this terminal", client.username),
+                    )
+                    .await;
+            }
+            conn.shadowing.insert(shadow.id, shadow.notify);
+            Ok(None)
+        }
+
+        WsMessage::TerminalDetach(detach) => {
+            state
+                .session_manager
+                .detach_terminal(&detach.id, &client.connection_id)
+                .await;
+            if let Some(notify) = conn.shadowing.remove(&detach.id) {
+                end_shadow(state, &conn.client, &detach.id, notify).await;
             }
+            Ok(None)
+        }
+
+        WsMessage::TerminalGrant(access) => {
+            if conn.role == Role::Viewer {
+                tracing::warn!("Viewer {} may not share terminals", client.username);
+                return Err(RequestError::new(
+                    Some(&access.id),
+                    ErrorCode::AccessDenied,
+                    "Viewers cannot share terminals",
+                ));
+            }
+            if !terminal_acl_allows(state, &client.username, &access.id, acl::Action::Share).await {
+                return Err(RequestError::new(
+                    Some(&access.id),
+                    ErrorCode::AccessDenied,
+                    "Access denied",
+                ));
+            }
+            state
+                .session_manager
+                .grant_access(&access.id, &client.username, &access.username)
+                .await
+                .map_err(|e| {
+                    tracing::warn!("Failed to share terminal {}: {}", access.id, e);
+                    terminal_error(&access.id, &e)
+                })?;
+            Ok(None)
+        }
+
+        WsMessage::TerminalRevoke(access) => {
+            state
+                .session_manager
+                .revoke_access(&access.id, &client.username, &access.username)
+                .await
+                .map_err(|e| {
+                    tracing::warn!("Failed to unshare terminal {}: {}", access.id, e);
+                    terminal_error(&access.id, &e)
+                })?;
+            Ok(None)
+        }
+
+        WsMessage::TerminalControl(control) => {
+            state
+                .session_manager
+                .set_controller(&control.id, &client.username, &control.connection_id)
+                .await
+                .map_err(|e| {
+                    tracing::warn!("Failed to pass control of terminal {}: {}", control.id, e);
+                    terminal_error(&control.id, &e)
+                })?;
+            Ok(None)
+        }
+
+        WsMessage::TerminalInput(input) => {
+            state
+                .session_manager
+                .write_to_terminal(&input.id, &client.connection_id, &input.input)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Failed to write to terminal {}: {}", input.id, e);
+                    terminal_error(&input.id, &e)
+                })?;
+            Ok(None)
+        }
+
+        WsMessage::TerminalResize(resize) => {
+            state
+                .session_manager
+                .resize_terminal(&resize.id, &client.connection_id, resize.cols, resize.rows)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Failed to resize terminal {}: {}", resize.id, e);
+                    terminal_error(&resize.id, &e)
+                })?;
+            Ok(None)
+        }
+
+        WsMessage::TerminalSignal(signal) => {
+            state
+                .session_manager
+                .signal_terminal(
+                    &signal.id,
+                    &client.connection_id,
+                    signal.signal,
+                    signal.target,
+                )
+                .await
+                .map_err(|e| {
+                    tracing::warn!("Failed to signal terminal {}: {}", signal.id, e);
+                    terminal_error(&signal.id, &e)
+                })?;
+            Ok(None)
         }
 
         WsMessage::TerminalClose(close) => {
+            // Only the owner closes the shell; other participants just leave
+            if !state
+                .session_manager
+                .is_owner(&close.id, &client.username)
+                .await
+            {
+                state
+                    .session_manager
+                    .detach_terminal(&close.id, &client.connection_id)
+                    .await;
+                if let Some(notify) = conn.shadowing.remove(&close.id) {
+                    end_shadow(state, &conn.client, &close.id, notify).await;
+                }
+                return Ok(None);
+            }
+
             tracing::info!("Closing terminal: {}", close.id);
-            state.session_manager.close_terminal(&close.id).await;
+            state
+                .session_manager
+                .close_terminal(&close.id)
+                .await
+                .map_err(|e| {
+                    tracing::warn!("Error closing terminal {}: {}", close.id, e);
+                    terminal_error(&close.id, &e)
+                })?;
+            Ok(None)
         }
 
-        // Server-to-client messages - ignore if received from client
-        WsMessage::ShellOutput(_) | WsMessage::ShellExit(_) => {}
+        // Server-to-client messages - rejected if received from client
+        WsMessage::ShellOutput(_)
+        | WsMessage::ShellExit(_)
+        | WsMessage::TerminalNotice(_)
+        | WsMessage::TerminalRole(_)
+        | WsMessage::TerminalResized(_)
+        | WsMessage::TerminalParticipants(_)
+        | WsMessage::ServerHello(_)
+        | WsMessage::TmuxSessions(_)
+        | WsMessage::TerminalTerminals(_)
+        | WsMessage::TerminalUpdated(_)
+        | WsMessage::TerminalStatus(_)
+        | WsMessage::TerminalOpened(_)
+        | WsMessage::TerminalError(_)
+        | WsMessage::Ack
+        | WsMessage::ServerShutdown(_) => Err(RequestError::new(
+            None,
+            ErrorCode::BadRequest,
+            "Not a client message",
+        )),
     }
 }
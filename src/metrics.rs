@@ -0,0 +1,120 @@
+//! Prometheus metrics
+//!
+//! `GET /metrics` serves per-terminal resource usage in the Prometheus text
+//! format. It is off unless `metrics_token` is set, and scrapers must send
+//! that token as a bearer token.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+use crate::terminal::session::TerminalInfo;
+use crate::terminal::usage::ResourceUsage;
+use crate::AppState;
+
+/// Serve the metrics to an authorized scraper
+pub async fn handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(token) = state.config.get().metrics_token.clone() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Compare digests so the comparison time does not depend on the token
+    if presented.map(Sha256::digest) != Some(Sha256::digest(&token)) {
+        return (StatusCode::UNAUTHORIZED, "Invalid metrics token").into_response();
+    }
+
+    let terminals = state.session_manager.list_terminals().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&terminals),
+    )
+        .into_response()
+}
+
+/// Metric name, help text and value
+type Gauge = (&'static str, &'static str, fn(&ResourceUsage) -> f64);
+
+/// Per-terminal gauges, labelled with the terminal id and owner
+const GAUGES: [Gauge; 6] = [
+    (
+        "webshell_terminal_cpu_seconds",
+        "CPU time used by the terminal's processes",
+        |u| u.cpu_secs,
+    ),
+    (
+        "webshell_terminal_cpu_percent",
+        "CPU use of the terminal's processes (100 = one core)",
+        |u| u.cpu_percent,
+    ),
+    (
+        "webshell_terminal_rss_bytes",
+        "Resident memory of the terminal's processes",
+        |u| u.rss_bytes as f64,
+    ),
+    (
+        "webshell_terminal_processes",
+        "Processes running in the terminal",
+        |u| u.processes as f64,
+    ),
+    (
+        "webshell_terminal_read_bytes",
+        "Bytes the terminal's processes read from storage",
+        |u| u.read_bytes as f64,
+    ),
+    (
+        "webshell_terminal_write_bytes",
+        "Bytes the terminal's processes wrote to storage",
+        |u| u.write_bytes as f64,
+    ),
+];
+
+/// Render terminal metrics in the Prometheus text format
+fn render(terminals: &[TerminalInfo]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# HELP webshell_terminals Open terminals");
+    let _ = writeln!(out, "# TYPE webshell_terminals gauge");
+    let _ = writeln!(out, "webshell_terminals {}", terminals.len());
+
+    for (name, help, value) in GAUGES {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for terminal in terminals {
+            if let Some(usage) = &terminal.usage {
+                let _ = writeln!(
+                    out,
+                    "{}{{terminal=\"{}\",owner=\"{}\"}} {}",
+                    name,
+                    escape(&terminal.id),
+                    escape(&terminal.owner),
+                    value(usage)
+                );
+            }
+        }
+    }
+    out
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
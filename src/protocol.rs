@@ -0,0 +1,176 @@
+//! WebSocket protocol handshake
+//!
+//! Every connection starts with the client's `hello`, carrying the highest
+//! protocol version it speaks and the optional features it supports. The
+//! server answers with `server.hello`: the version to use, the features both
+//! sides support, its limits and who the connection is authenticated as.
+//! Clients that skip the greeting or are too old are closed with one of the
+//! [`close`] codes.
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use std::borrow::Cow;
+use std::time::Duration;
+
+use crate::types::{Hello, WsMessage};
+
+/// Current protocol version
+pub const VERSION: u32 = 1;
+
+/// Oldest protocol version still served
+pub const MIN_VERSION: u32 = 1;
+
+/// How long a client has to send its `hello`
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Optional protocol features
+pub mod capability {
+    /// Attach to existing (owned or shared) terminals
+    pub const ATTACH: &str = "attach";
+    /// Admin shadowing of any terminal
+    pub const SHADOW: &str = "shadow";
+    /// Named tmux sessions (`tmux.list`, `term.open` `session`)
+    pub const TMUX: &str = "tmux";
+    /// Terminals survive server restarts
+    pub const PERSISTENT: &str = "persistent";
+}
+
+/// WebSocket close codes (application range)
+pub mod close {
+    /// The client's protocol version is too old
+    pub const UNSUPPORTED_VERSION: u16 = 4001;
+    /// The first message was not a valid `hello`
+    pub const HELLO_REQUIRED: u16 = 4002;
+}
+
+/// Why a handshake failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl Rejection {
+    fn new(code: u16, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Agreed protocol version and features
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+}
+
+/// Settle on a version and the features both sides support
+pub fn negotiate(hello: &Hello, supported: &[&str]) -> Result<Negotiated, Rejection> {
+    if hello.version < MIN_VERSION {
+        return Err(Rejection::new(
+            close::UNSUPPORTED_VERSION,
+            format!(
+                "Protocol version {} is not supported (need {} to {})",
+                hello.version, MIN_VERSION, VERSION
+            ),
+        ));
+    }
+
+    let capabilities = supported
+        .iter()
+        .filter(|c| hello.capabilities.iter().any(|h| h == *c))
+        .map(|c| c.to_string())
+        .collect();
+    Ok(Negotiated {
+        version: hello.version.min(VERSION),
+        capabilities,
+    })
+}
+
+/// Wait for the client's `hello` and negotiate
+///
+/// Returns `Ok(None)` if the client went away first.
+pub async fn handshake(
+    receiver: &mut SplitStream<WebSocket>,
+    supported: &[&str],
+) -> Result<Option<Negotiated>, Rejection> {
+    match receive_hello(receiver).await? {
+        Some(hello) => negotiate(&hello, supported).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Wait for the client's `hello`
+///
+/// Anything else first, or nothing within [`HELLO_TIMEOUT`], is rejected.
+/// Returns `Ok(None)` if the client went away.
+async fn receive_hello(receiver: &mut SplitStream<WebSocket>) -> Result<Option<Hello>, Rejection> {
+    let first = async {
+        loop {
+            match receiver.next().await {
+                Some(Ok(Message::Text(text))) => return Some(text),
+                // Pings and pongs may come first
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return None,
+                // Not JSON, so rejected below
+                Some(Ok(Message::Binary(_))) => return Some(String::new()),
+            }
+        }
+    };
+
+    let text = match tokio::time::timeout(HELLO_TIMEOUT, first).await {
+        Ok(Some(text)) => text,
+        Ok(None) => return Ok(None),
+        Err(_) => return Err(Rejection::new(close::HELLO_REQUIRED, "No hello received")),
+    };
+
+    match serde_json::from_str::<WsMessage>(&text) {
+        Ok(WsMessage::Hello(hello)) => Ok(Some(hello)),
+        _ => Err(Rejection::new(
+            close::HELLO_REQUIRED,
+            "Expected hello as the first message",
+        )),
+    }
+}
+
+/// Close the connection with a rejection's code and reason
+pub async fn reject(sender: &mut SplitSink<WebSocket, Message>, rejection: Rejection) {
+    let _ = sender
+        .send(Message::Close(Some(CloseFrame {
+            code: rejection.code,
+            reason: Cow::Owned(rejection.reason),
+        })))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(version: u32, capabilities: &[&str]) -> Hello {
+        Hello {
+            version,
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let supported = [capability::ATTACH, capability::TMUX];
+
+        let agreed = negotiate(&hello(1, &["tmux", "binary", "attach"]), &supported).unwrap();
+        assert_eq!(agreed.version, 1);
+        assert_eq!(agreed.capabilities, vec!["attach", "tmux"]);
+
+        // Newer clients are served at our version
+        let agreed = negotiate(&hello(VERSION + 1, &[]), &supported).unwrap();
+        assert_eq!(agreed.version, VERSION);
+        assert!(agreed.capabilities.is_empty());
+
+        let rejected = negotiate(&hello(0, &[]), &supported).unwrap_err();
+        assert_eq!(rejected.code, close::UNSUPPORTED_VERSION);
+    }
+}
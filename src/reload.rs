@@ -0,0 +1,226 @@
+//! Configuration hot reload
+//!
+//! Re-reads the config file (and env and flags) on SIGHUP or an admin request
+//! and swaps the result in without touching running terminals. Settings that
+//! are baked into listeners or files at startup keep their running values
+//! until the next restart; everything else applies immediately.
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::audit::AuditLog;
+use crate::config::{Config, ConfigError, SharedConfig};
+
+/// Keys that only take effect after a restart
+const RESTART_ONLY: &[&str] = &[
+    "port",
+    "listen",
+    "socket_mode",
+    "socket_owner",
+    "audit_log",
+    "data_dir",
+    "supervisor_socket",
+    "tls",
+    "tls_cert",
+    "tls_key",
+    "tls_redirect_port",
+    "tls_client_ca",
+    "tls_client_required",
+];
+
+/// A changed setting
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigChange {
+    pub key: String,
+    /// Applied now, rather than after a restart
+    pub live: bool,
+    /// Previous value, if set and printable (secrets are redacted)
+    pub from: Option<String>,
+    /// New value, if set and printable
+    pub to: Option<String>,
+}
+
+/// Reload the configuration, returning what changed
+///
+/// On error the running configuration is left untouched.
+pub fn reload(
+    config: &SharedConfig,
+    audit: &AuditLog,
+    actor: &str,
+) -> Result<Vec<ConfigChange>, ConfigError> {
+    let mut new = config.load()?;
+    let old = config.get();
+
+    let changes = diff(&old, &new);
+    keep_restart_only(&old, &mut new);
+    config.replace(new);
+
+    if changes.is_empty() {
+        tracing::info!("Configuration reloaded, nothing changed");
+    }
+    for change in &changes {
+        let values = match (&change.from, &change.to) {
+            (None, None) => String::new(),
+            (from, to) => format!(
+                ": {} -> {}",
+                from.as_deref().unwrap_or("(unset)"),
+                to.as_deref().unwrap_or("(unset)")
+            ),
+        };
+        if change.live {
+            tracing::info!("Configuration reloaded: {} changed{}", change.key, values);
+        } else {
+            tracing::warn!(
+                "Configuration reloaded: {} changed{} (takes effect after restart)",
+                change.key,
+                values
+            );
+        }
+    }
+
+    audit.record(
+        actor,
+        "config.reload",
+        "",
+        serde_json::json!({ "changes": changes }),
+    );
+    Ok(changes)
+}
+
+/// Settings that differ between two configurations
+fn diff(old: &Config, new: &Config) -> Vec<ConfigChange> {
+    let table = |config: &Config| match toml::Value::try_from(config.redacted()) {
+        Ok(toml::Value::Table(table)) => table,
+        _ => toml::Table::new(),
+    };
+    let (old_table, new_table) = (table(old), table(new));
+
+    let mut keys: BTreeSet<&String> = old_table.keys().chain(new_table.keys()).collect();
+    keys.retain(|key| old_table.get(*key) != new_table.get(*key));
+    let mut changes: Vec<ConfigChange> = keys
+        .into_iter()
+        .map(|key| {
+            let (from, to) = (old_table.get(key), new_table.get(key));
+            // Show both values or neither
+            let shown = from.into_iter().chain(to).all(|v| printable(v).is_some());
+            ConfigChange {
+                key: key.clone(),
+                live: !RESTART_ONLY.contains(&key.as_str()),
+                from: from.filter(|_| shown).and_then(printable),
+                to: to.filter(|_| shown).and_then(printable),
+            }
+        })
+        .collect();
+
+    // Secrets are redacted above, so compare them directly
+    let secret_changes = [
+        (
+            "credentials",
+            &["password", "ssh_key", "ssh_key_data", "ssh_passphrase"][..],
+            old.auth != new.auth,
+        ),
+        ("profiles", &["profiles"][..], old.profiles != new.profiles),
+        (
+            "metrics_token",
+            &["metrics_token"][..],
+            old.metrics_token != new.metrics_token,
+        ),
+    ];
+    for (key, keys, changed) in secret_changes {
+        if changed && !changes.iter().any(|c| keys.contains(&c.key.as_str())) {
+            changes.push(ConfigChange {
+                key: key.to_string(),
+                live: true,
+                from: None,
+                to: None,
+            });
+        }
+    }
+    changes
+}
+
+/// Short display form of a value (tables and lists of tables are omitted)
+fn printable(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Table(_) => None,
+        toml::Value::Array(items) if items.iter().any(|v| v.is_table()) => None,
+        other => Some(other.to_string()),
+    }
+}
+
+/// Carry over settings that cannot change while running
+fn keep_restart_only(old: &Config, new: &mut Config) {
+    new.port = old.port;
+    new.listen = old.listen.clone();
+    new.audit_log = old.audit_log.clone();
+    new.data_dir = old.data_dir.clone();
+    new.supervisor_socket = old.supervisor_socket.clone();
+    new.tls = old.tls;
+    new.tls_cert = old.tls_cert.clone();
+    new.tls_key = old.tls_key.clone();
+    new.tls_redirect_port = old.tls_redirect_port;
+    new.tls_client_ca = old.tls_client_ca.clone();
+    new.tls_client_required = old.tls_client_required;
+}
+
+/// Reload the configuration whenever the process receives SIGHUP
+#[cfg(unix)]
+pub fn spawn_sighup_handler(config: SharedConfig, audit: AuditLog) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::error!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            tracing::info!("SIGHUP received, reloading configuration");
+            if let Err(e) = reload(&config, &audit, "SIGHUP") {
+                tracing::error!("Failed to reload configuration, keeping current: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+
+    #[test]
+    fn test_diff() {
+        let old = Config::default();
+        let new = Config::from_settings(Settings {
+            port: Some(4000),
+            max_terminals: Some(3),
+            password: Some("secret".to_string()),
+            ..Settings::default()
+        })
+        .unwrap();
+
+        let changes = diff(&old, &new);
+        let keys: Vec<(&str, bool)> = changes.iter().map(|c| (c.key.as_str(), c.live)).collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("listen", false),
+                ("max_terminals", true),
+                ("password", true),
+                ("port", false)
+            ]
+        );
+        assert_eq!(changes[1].to.as_deref(), Some("3"));
+        assert_eq!(changes[2].to.as_deref(), Some("<redacted>"));
+
+        let mut kept = new.clone();
+        keep_restart_only(&old, &mut kept);
+        assert_eq!(kept.port, old.port);
+        assert_eq!(kept.max_terminals, 3);
+        assert!(diff(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+//! HTTP(S) server
+//!
+//! Listeners (TCP or Unix domain sockets), the accept loop serving the router
+//! over plain HTTP or TLS, plus the optional HTTP-to-HTTPS redirect listener.
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+use crate::config::Listen;
+use crate::tls::PeerCertificate;
+
+/// Pending connection backlog
+const BACKLOG: i32 = 1024;
+
+/// Time a client gets to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Peer address reported for Unix socket connections
+///
+/// Unix peers are local processes, typically a reverse proxy.
+const UNIX_PEER: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 0);
+
+/// A bound listener
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Bind a configured listen address
+    pub fn bind(listen: &Listen) -> io::Result<Self> {
+        match listen {
+            Listen::Tcp(addr) => bind_tcp(*addr).map(Listener::Tcp),
+            Listen::Unix { path, mode, owner } => {
+                bind_unix(path, *mode, owner.as_deref()).map(Listener::Unix)
+            }
+        }
+    }
+}
+
+/// Bind a TCP listener, making `[::]` dual-stack regardless of system default
+fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Bind a Unix socket, replacing a stale one and applying mode and owner
+pub(crate) fn bind_unix(
+    path: &Path,
+    mode: Option<u32>,
+    owner: Option<&str>,
+) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    // A leftover socket from a previous run would make bind fail
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    if let Some(owner) = owner {
+        let (uid, gid) = resolve_owner(owner)?;
+        std::os::unix::fs::chown(path, uid, gid)?;
+    }
+    Ok(listener)
+}
+
+/// Resolve `user` or `user:group` to ids
+fn resolve_owner(owner: &str) -> io::Result<(Option<u32>, Option<u32>)> {
+    use nix::unistd::{Group, User};
+
+    let not_found = |what: &str| io::Error::new(io::ErrorKind::NotFound, what.to_string());
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+
+    let uid = match user {
+        "" => None,
+        user => Some(
+            User::from_name(user)?
+                .ok_or_else(|| not_found(&format!("No such user: {}", user)))?
+                .uid
+                .as_raw(),
+        ),
+    };
+    let gid = match group {
+        None | Some("") => None,
+        Some(group) => Some(
+            Group::from_name(group)?
+                .ok_or_else(|| not_found(&format!("No such group: {}", group)))?
+                .gid
+                .as_raw(),
+        ),
+    };
+    Ok((uid, gid))
+}
+
+/// Serve the app on a listener, terminating TLS if an acceptor is given
+pub async fn serve(listener: Listener, app: Router, tls: Option<TlsAcceptor>) {
+    loop {
+        let accepted = match &listener {
+            Listener::Tcp(listener) => listener.accept().await.map(|(stream, addr)| {
+                tokio::spawn(accept(stream, addr, app.clone(), tls.clone()));
+            }),
+            Listener::Unix(listener) => listener.accept().await.map(|(stream, _)| {
+                tokio::spawn(accept(stream, UNIX_PEER, app.clone(), tls.clone()));
+            }),
+        };
+
+        if let Err(e) = accepted {
+            // Usually fd exhaustion; back off instead of spinning
+            tracing::error!("Failed to accept connection: {}", e);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+/// Complete the TLS handshake if needed, then serve the connection
+async fn accept<S>(stream: S, addr: SocketAddr, app: Router, tls: Option<TlsAcceptor>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    match tls {
+        Some(acceptor) => {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    // Only present if the client CA verified it
+                    let peer_cert = stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(|certs| certs.first())
+                        .map(|cert| PeerCertificate(cert.clone().into_owned()));
+                    serve_connection(stream, addr, peer_cert, app).await
+                }
+                Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", addr, e),
+                Err(_) => tracing::debug!("TLS handshake with {} timed out", addr),
+            }
+        }
+        None => serve_connection(stream, addr, None, app).await,
+    }
+}
+
+/// Serve HTTP on a single accepted connection
+async fn serve_connection<S>(
+    stream: S,
+    addr: SocketAddr,
+    peer_cert: Option<PeerCertificate>,
+    app: Router,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = hyper::service::service_fn(move |mut req: Request<Incoming>| {
+        req.extensions_mut().insert(ConnectInfo(addr));
+        if let Some(cert) = &peer_cert {
+            req.extensions_mut().insert(cert.clone());
+        }
+        app.clone().oneshot(req)
+    });
+
+    if let Err(e) = auto::Builder::new(TokioExecutor::new())
+        .serve_connection_with_upgrades(TokioIo::new(stream), service)
+        .await
+    {
+        tracing::debug!("Connection from {} ended with error: {}", addr, e);
+    }
+}
+
+/// Router that redirects every request to HTTPS on `https_port`
+pub fn redirect_app(https_port: u16) -> Router {
+    Router::new().fallback(move |uri: Uri, headers: axum::http::HeaderMap| async move {
+        redirect_to_https(uri, &headers, https_port)
+    })
+}
+
+fn redirect_to_https(uri: Uri, headers: &axum::http::HeaderMap, https_port: u16) -> Response {
+    let Some(host) = headers.get(header::HOST).and_then(|h| h.to_str().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+
+    // Replace any port in the Host header with the HTTPS port
+    let hostname = match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let authority = if https_port == 443 {
+        hostname.to_string()
+    } else {
+        format!("{}:{}", hostname, https_port)
+    };
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+    Redirect::permanent(&format!("https://{}{}", authority, path)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_redirect_listener() {
+        let listener = Listener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let Listener::Tcp(tcp) = &listener else {
+            panic!("not a TCP listener");
+        };
+        let addr = tcp.local_addr().unwrap();
+        assert!(addr.ip().is_loopback());
+        tokio::spawn(serve(listener, redirect_app(8443), None));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"GET /ws?x=1 HTTP/1.1\r\nHost: example.com:8080\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 308"));
+        assert!(response
+            .to_ascii_lowercase()
+            .contains("location: https://example.com:8443/ws?x=1"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tls_handshake_timeout() {
+        let dir = std::env::temp_dir().join(format!("webshell-tls-{}", std::process::id()));
+        let (cert, key) = crate::tls::ensure_self_signed(dir.to_str().unwrap()).unwrap();
+        let acceptor = crate::tls::acceptor(&cert, &key, None).unwrap();
+
+        let listener = Listener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let Listener::Tcp(tcp) = &listener else {
+            panic!("not a TCP listener");
+        };
+        let addr = tcp.local_addr().unwrap();
+        tokio::spawn(serve(listener, Router::new(), Some(acceptor)));
+
+        // A client that never starts the handshake is dropped
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0; 1];
+        let read = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT * 2, stream.read(&mut buf)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bind_unix() {
+        let dir = std::env::temp_dir().join(format!("webshell-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("webshell.sock");
+        let listen: Listen = format!("unix:{}", path.display()).parse().unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            assert!(matches!(Listener::bind(&listen), Ok(Listener::Unix(_))));
+            // A stale socket from a previous run is replaced
+            assert!(matches!(Listener::bind(&listen), Ok(Listener::Unix(_))));
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
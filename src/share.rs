@@ -0,0 +1,423 @@
+//! Expiring share links for terminals
+//!
+//! An owner creates a signed, time-limited link to one of their terminals.
+//! Opening the link exchanges it for a guest session cookie scoped to that
+//! terminal, so viewers can attach without a full login.
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, post},
+    Form, Json, Router,
+};
+use axum_extra::extract::cookie::CookieJar;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::acl::Action;
+use crate::auth::Role;
+use crate::{current_session, session_cookie, terminal_acl_allows, AppState, SHARE_COOKIE};
+
+/// Default link lifetime (seconds)
+const DEFAULT_TTL: i64 = 60 * 60;
+
+/// Start of guest usernames, which real logins may not use
+pub const GUEST_PREFIX: &str = "guest-";
+
+/// Longest allowed link lifetime (seconds)
+const MAX_TTL: i64 = 7 * 24 * 60 * 60;
+
+/// What a share link viewer may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareScope {
+    /// Always a spectator
+    ReadOnly,
+    /// May be handed input control by the owner
+    ReadWrite,
+}
+
+/// A share link for a terminal
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareLink {
+    pub id: String,
+    pub terminal_id: String,
+    pub owner: String,
+    /// Username viewers appear under
+    pub guest: String,
+    pub scope: ShareScope,
+    pub max_viewers: Option<usize>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ShareLink {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
+
+/// Share link store with the signing key
+#[derive(Clone)]
+pub struct ShareStore {
+    secret: Arc<[u8; 32]>,
+    links: Arc<RwLock<HashMap<String, ShareLink>>>,
+}
+
+impl Default for ShareStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShareStore {
+    /// Create a store with a fresh random signing key
+    ///
+    /// Links do not survive a restart.
+    pub fn new() -> Self {
+        Self {
+            secret: Arc::new(rand::thread_rng().gen()),
+            links: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Create a link and return it with its signed token
+    pub async fn create(
+        &self,
+        terminal_id: &str,
+        owner: &str,
+        scope: ShareScope,
+        ttl_secs: Option<i64>,
+        max_viewers: Option<usize>,
+    ) -> (ShareLink, String) {
+        let id = uuid::Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let ttl = ttl_secs.unwrap_or(DEFAULT_TTL).clamp(1, MAX_TTL);
+        let link = ShareLink {
+            guest: format!("{}{}", GUEST_PREFIX, &id[..8]),
+            id,
+            terminal_id: terminal_id.to_string(),
+            owner: owner.to_string(),
+            scope,
+            max_viewers,
+            created_at: now,
+            expires_at: now + Duration::seconds(ttl),
+        };
+        let token = self.sign(&link.id, link.expires_at.timestamp());
+
+        let mut links = self.links.write().await;
+        links.retain(|_, l| !l.is_expired());
+        links.insert(link.id.clone(), link.clone());
+        (link, token)
+    }
+
+    /// Verify a token and return its live link
+    pub async fn verify(&self, token: &str) -> Result<ShareLink, String> {
+        let mut parts = token.splitn(3, '.');
+        let (Some(id), Some(expires), Some(signature)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err("Malformed share link".to_string());
+        };
+
+        let signature = hex::decode(signature).map_err(|_| "Malformed share link".to_string())?;
+        self.mac(id, expires)
+            .verify_slice(&signature)
+            .map_err(|_| "Invalid share link".to_string())?;
+
+        let links = self.links.read().await;
+        match links.get(id) {
+            Some(link) if link.is_expired() => Err("Share link expired".to_string()),
+            Some(link) => Ok(link.clone()),
+            None => Err("Share link revoked".to_string()),
+        }
+    }
+
+    /// Look up a link by id
+    pub async fn get(&self, id: &str) -> Option<ShareLink> {
+        self.links.read().await.get(id).cloned()
+    }
+
+    /// List live links for a terminal
+    pub async fn list(&self, terminal_id: &str) -> Vec<ShareLink> {
+        let links = self.links.read().await;
+        let mut list: Vec<ShareLink> = links
+            .values()
+            .filter(|l| l.terminal_id == terminal_id && !l.is_expired())
+            .cloned()
+            .collect();
+        list.sort_by_key(|l| l.created_at);
+        list
+    }
+
+    /// Revoke a link (owner only)
+    pub async fn revoke(&self, id: &str, owner: &str) -> Option<ShareLink> {
+        let mut links = self.links.write().await;
+        if links.get(id)?.owner != owner {
+            return None;
+        }
+        links.remove(id)
+    }
+
+    fn sign(&self, id: &str, expires: i64) -> String {
+        let expires = expires.to_string();
+        let signature = hex::encode(self.mac(id, &expires).finalize().into_bytes());
+        format!("{}.{}.{}", id, expires, signature)
+    }
+
+    fn mac(&self, id: &str, expires: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.secret.as_slice()).expect("HMAC accepts any key");
+        mac.update(id.as_bytes());
+        mac.update(b".");
+        mac.update(expires.as_bytes());
+        mac
+    }
+}
+
+/// Share routes, mounted under `/api/shares`
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", post(create_share).get(list_shares))
+        .route("/:id", delete(revoke_share))
+        .route("/join", post(join_share))
+}
+
+/// Create share link request
+#[derive(Debug, Deserialize)]
+struct CreateShareRequest {
+    terminal_id: String,
+    scope: ShareScope,
+    ttl_secs: Option<i64>,
+    max_viewers: Option<usize>,
+}
+
+/// Create share link response
+#[derive(Debug, Serialize)]
+struct CreateShareResponse {
+    #[serde(flatten)]
+    link: ShareLink,
+    url: String,
+}
+
+/// Create a share link for one of the caller's terminals
+async fn create_share(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(req): Json<CreateShareRequest>,
+) -> Response {
+    let Some(session) = current_session(&state, &jar).await else {
+        return (StatusCode::UNAUTHORIZED, "Authentication required").into_response();
+    };
+    if session.role == Role::Viewer {
+        return (StatusCode::FORBIDDEN, "Viewers cannot share terminals").into_response();
+    }
+    let owner = session.username;
+
+    if !state
+        .session_manager
+        .is_owner(&req.terminal_id, &owner)
+        .await
+    {
+        return (StatusCode::FORBIDDEN, "Not the terminal owner").into_response();
+    }
+
+    if !terminal_acl_allows(&state, &owner, &req.terminal_id, Action::Share).await {
+        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+    }
+
+    let (link, token) = state
+        .shares
+        .create(
+            &req.terminal_id,
+            &owner,
+            req.scope,
+            req.ttl_secs,
+            req.max_viewers,
+        )
+        .await;
+
+    // Access is keyed by the link, not the guest name it shows
+    if let Err(e) = state
+        .session_manager
+        .grant_link(&link.terminal_id, &owner, &link.id, link.max_viewers)
+        .await
+    {
+        tracing::error!("Failed to grant share link access: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+
+    tracing::info!(
+        "User {} created {:?} share link {} for terminal {} (expires {})",
+        owner,
+        link.scope,
+        link.id,
+        link.terminal_id,
+        link.expires_at
+    );
+
+    Json(CreateShareResponse {
+        url: format!("/#share={}", token),
+        link,
+    })
+    .into_response()
+}
+
+/// List share links query
+#[derive(Debug, Deserialize)]
+struct ListSharesQuery {
+    terminal_id: String,
+}
+
+/// List live share links for one of the caller's terminals
+async fn list_shares(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(query): Query<ListSharesQuery>,
+) -> Response {
+    let Some(session) = current_session(&state, &jar).await else {
+        return (StatusCode::UNAUTHORIZED, "Authentication required").into_response();
+    };
+
+    if !state
+        .session_manager
+        .is_owner(&query.terminal_id, &session.username)
+        .await
+    {
+        return (StatusCode::FORBIDDEN, "Not the terminal owner").into_response();
+    }
+
+    Json(state.shares.list(&query.terminal_id).await).into_response()
+}
+
+/// Revoke a share link, disconnecting its viewers
+async fn revoke_share(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(session) = current_session(&state, &jar).await else {
+        return (StatusCode::UNAUTHORIZED, "Authentication required").into_response();
+    };
+    let owner = session.username;
+
+    let Some(link) = state.shares.revoke(&id, &owner).await else {
+        return (StatusCode::NOT_FOUND, "Share link not found").into_response();
+    };
+
+    if let Err(e) = state
+        .session_manager
+        .revoke_link(&link.terminal_id, &owner, &link.id)
+        .await
+    {
+        tracing::warn!("Failed to detach share link viewers: {}", e);
+    }
+    state.auth_sessions.remove_share_sessions(&link.id).await;
+
+    tracing::info!("User {} revoked share link {}", owner, link.id);
+    Json(serde_json::json!({"success": true})).into_response()
+}
+
+/// Join share link request
+#[derive(Debug, Deserialize)]
+struct JoinShareRequest {
+    token: String,
+}
+
+/// Exchange a share link for a guest session cookie
+async fn join_share(
+    State(state): State<AppState>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    jar: CookieJar,
+    Form(req): Form<JoinShareRequest>,
+) -> impl IntoResponse {
+    if state.shutdown.is_started() {
+        return (
+            jar,
+            Json(serde_json::json!({"success": false, "message": "Server is shutting down"})),
+        );
+    }
+
+    let link = match state.shares.verify(&req.token).await {
+        Ok(link) => link,
+        Err(e) => {
+            tracing::warn!("Share link rejected from {}: {}", client_addr, e);
+            return (
+                jar,
+                Json(serde_json::json!({"success": false, "message": e})),
+            );
+        }
+    };
+
+    let token = state
+        .auth_sessions
+        .create_share_session(
+            link.guest.clone(),
+            Some(client_addr.ip()),
+            link.id.clone(),
+            link.expires_at,
+        )
+        .await;
+    tracing::info!(
+        "Share link {} opened from {} for terminal {}",
+        link.id,
+        client_addr,
+        link.terminal_id
+    );
+
+    (
+        // Separate from the login cookie, so opening a link keeps any login
+        jar.add(session_cookie(
+            SHARE_COOKIE,
+            token,
+            state.config.get().tls_enabled(),
+        )),
+        Json(serde_json::json!({
+            "success": true,
+            "username": link.guest,
+            "terminal_id": link.terminal_id,
+            "scope": link.scope,
+        })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_share_token() {
+        let store = ShareStore::new();
+        let (link, token) = store
+            .create("term-1", "alice", ShareScope::ReadOnly, Some(60), None)
+            .await;
+
+        let verified = store.verify(&token).await.unwrap();
+        assert_eq!(verified.id, link.id);
+        assert_eq!(verified.terminal_id, "term-1");
+
+        // Tampering with the expiry breaks the signature
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let later = (link.expires_at.timestamp() + 3600).to_string();
+        parts[1] = &later;
+        assert!(store.verify(&parts.join(".")).await.is_err());
+
+        // Tokens from another key are rejected
+        let other = ShareStore::new();
+        assert!(other.verify(&token).await.is_err());
+
+        assert!(store.revoke(&link.id, "mallory").await.is_none());
+        assert!(store.revoke(&link.id, "alice").await.is_some());
+        assert_eq!(
+            store.verify(&token).await.unwrap_err(),
+            "Share link revoked"
+        );
+    }
+}
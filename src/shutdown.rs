@@ -0,0 +1,68 @@
+//! Graceful shutdown
+//!
+//! On SIGTERM or SIGINT the server stops taking logins, tells every
+//! connected client how long it has, waits out the grace period and then
+//! closes the remaining terminals. A second signal skips the wait.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Shutdown state shared with every connection
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    /// Grace period in seconds, once shutdown has begun
+    started: Arc<watch::Sender<Option<u64>>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            started: Arc::new(watch::channel(None).0),
+        }
+    }
+
+    /// Begin shutting down with a grace period
+    pub fn begin(&self, grace_secs: u64) {
+        self.started.send_replace(Some(grace_secs));
+    }
+
+    /// Check if shutdown has begun
+    pub fn is_started(&self) -> bool {
+        self.started.borrow().is_some()
+    }
+
+    /// Watch for shutdown beginning
+    pub fn subscribe(&self) -> watch::Receiver<Option<u64>> {
+        self.started.subscribe()
+    }
+}
+
+/// Wait for SIGTERM or SIGINT (Ctrl-C)
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
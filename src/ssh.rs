@@ -4,6 +4,10 @@ use async_trait::async_trait;
 use russh::*;
 use russh_keys::*;
 use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::config::AuthMethod;
 
 /// SSH authentication method
 #[derive(Debug, Clone)]
@@ -19,6 +23,24 @@ pub enum SshAuth {
     },
 }
 
+impl SshAuth {
+    /// SSH auth for a configured method, if one is set
+    pub fn from_method(method: &AuthMethod) -> Option<Self> {
+        match method {
+            AuthMethod::Password(p) => Some(SshAuth::Password(p.clone())),
+            AuthMethod::KeyFile { path, passphrase } => Some(SshAuth::KeyFile {
+                path: path.clone(),
+                passphrase: passphrase.clone(),
+            }),
+            AuthMethod::KeyData { data, passphrase } => Some(SshAuth::KeyData {
+                data: data.clone(),
+                passphrase: passphrase.clone(),
+            }),
+            AuthMethod::None => None,
+        }
+    }
+}
+
 /// SSH connection configuration
 #[derive(Debug, Clone)]
 pub struct SshConfig {
@@ -47,6 +69,12 @@ impl client::Handler for ClientHandler {
 
 /// Test SSH connection and authentication
 pub async fn test_connection(config: SshConfig) -> Result<String, String> {
+    connect(config).await?;
+    Ok("Connection successful".to_string())
+}
+
+/// Connect and authenticate
+async fn connect(config: SshConfig) -> Result<client::Handle<ClientHandler>, String> {
     let russh_config = client::Config::default();
     let config_arc = Arc::new(russh_config);
     let addr = format!("{}:{}", config.host, config.port);
@@ -83,5 +111,168 @@ pub async fn test_connection(config: SshConfig) -> Result<String, String> {
         return Err("Authentication failed".to_string());
     }
 
-    Ok("Connection successful".to_string())
+    Ok(session)
+}
+
+/// Remote shell settings
+#[derive(Debug, Clone, Default)]
+pub struct ShellOptions {
+    pub cols: u16,
+    pub rows: u16,
+    /// Run this instead of a login shell
+    pub command: Option<String>,
+    /// Working directory
+    pub cwd: Option<String>,
+    /// Environment variables (subject to the server's `AcceptEnv`)
+    pub env: Vec<(String, String)>,
+}
+
+/// A running remote shell
+pub struct SshShell {
+    pub input_tx: mpsc::Sender<Vec<u8>>,
+    resize_tx: mpsc::UnboundedSender<(u16, u16)>,
+    signal_tx: mpsc::UnboundedSender<Sig>,
+    task: JoinHandle<()>,
+}
+
+impl SshShell {
+    /// Change the remote PTY size
+    pub fn resize(&self, cols: u16, rows: u16) {
+        let _ = self.resize_tx.send((cols, rows));
+    }
+
+    /// Ask the server to signal the remote process
+    ///
+    /// Servers are free to ignore this; older OpenSSH releases do.
+    pub fn signal(&self, signal: Sig) {
+        let _ = self.signal_tx.send(signal);
+    }
+
+    /// Disconnect
+    pub fn close(&self) {
+        self.task.abort();
+    }
+}
+
+/// Open an interactive shell on a PTY, streaming its output to a callback
+pub async fn open_shell<F>(
+    config: SshConfig,
+    options: ShellOptions,
+    output_callback: F,
+) -> Result<SshShell, String>
+where
+    F: Fn(Vec<u8>) + Send + 'static,
+{
+    let host = config.host.clone();
+    let session = connect(config).await?;
+    let channel_error = |e: russh::Error| format!("SSH channel error: {}", e);
+
+    let mut channel = session
+        .channel_open_session()
+        .await
+        .map_err(channel_error)?;
+    channel
+        .request_pty(
+            false,
+            "xterm-256color",
+            options.cols.into(),
+            options.rows.into(),
+            0,
+            0,
+            &[],
+        )
+        .await
+        .map_err(channel_error)?;
+    for (name, value) in &options.env {
+        channel
+            .set_env(false, name.as_str(), value.as_str())
+            .await
+            .map_err(channel_error)?;
+    }
+    match remote_command(options.command.as_deref(), options.cwd.as_deref()) {
+        Some(command) => channel.exec(false, command).await,
+        None => channel.request_shell(false).await,
+    }
+    .map_err(channel_error)?;
+
+    let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
+    let (resize_tx, mut resize_rx) = mpsc::unbounded_channel::<(u16, u16)>();
+    let (signal_tx, mut signal_rx) = mpsc::unbounded_channel::<Sig>();
+
+    let task = tokio::spawn(async move {
+        // Keep the connection open as long as the channel
+        let _session = session;
+        loop {
+            tokio::select! {
+                msg = channel.wait() => match msg {
+                    Some(ChannelMsg::Data { data })
+                    | Some(ChannelMsg::ExtendedData { data, .. }) => output_callback(data.to_vec()),
+                    Some(ChannelMsg::ExitStatus { exit_status }) => {
+                        tracing::debug!("Remote shell on {} exited with {}", host, exit_status);
+                    }
+                    Some(ChannelMsg::Eof) | Some(ChannelMsg::Close) | None => break,
+                    Some(_) => {}
+                },
+                Some(data) = input_rx.recv() => {
+                    if let Err(e) = channel.data(&data[..]).await {
+                        tracing::debug!("SSH write to {} failed: {}", host, e);
+                        break;
+                    }
+                }
+                Some((cols, rows)) = resize_rx.recv() => {
+                    let _ = channel.window_change(cols.into(), rows.into(), 0, 0).await;
+                }
+                Some(signal) = signal_rx.recv() => {
+                    let _ = channel.signal(signal).await;
+                }
+            }
+        }
+        tracing::debug!("Remote shell on {} ended", host);
+    });
+
+    Ok(SshShell {
+        input_tx,
+        resize_tx,
+        signal_tx,
+        task,
+    })
+}
+
+/// Remote command for a shell with an optional command and working directory
+///
+/// SSH has no working directory request, so `cwd` becomes a `cd`.
+fn remote_command(command: Option<&str>, cwd: Option<&str>) -> Option<String> {
+    match (command, cwd) {
+        (None, None) => None,
+        (Some(command), None) => Some(command.to_string()),
+        (command, Some(cwd)) => Some(format!(
+            "cd {} && exec {}",
+            shell_quote(cwd),
+            command.unwrap_or("\"$SHELL\" -l")
+        )),
+    }
+}
+
+/// Quote a string for a POSIX shell
+pub(crate) fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_command() {
+        assert_eq!(remote_command(None, None), None);
+        assert_eq!(remote_command(Some("htop"), None).as_deref(), Some("htop"));
+        assert_eq!(
+            remote_command(None, Some("/srv/it's")).as_deref(),
+            Some("cd '/srv/it'\\''s' && exec \"$SHELL\" -l")
+        );
+        assert_eq!(
+            remote_command(Some("htop"), Some("/srv")).as_deref(),
+            Some("cd '/srv' && exec htop")
+        );
+    }
 }
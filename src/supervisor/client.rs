@@ -0,0 +1,402 @@
+//! Server side of the supervisor connection
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, oneshot};
+
+use super::{Event, Request, TerminalSnapshot};
+use crate::terminal::cgroup::CgroupSpec;
+use crate::terminal::error::TerminalError;
+use crate::terminal::sandbox::SandboxSpec;
+use crate::types::{Signal, SignalTarget};
+
+/// Receives a terminal's output
+type OutputCallback = Box<dyn Fn(Vec<u8>) + Send>;
+
+/// Answer to a spawn request: the shell's pid, or why it failed
+type SpawnAnswer = oneshot::Sender<Result<Option<u32>, String>>;
+
+/// How long to wait for a freshly spawned supervisor to listen
+const SPAWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lock a mutex, recovering from poisoning
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Default)]
+struct Shared {
+    /// Output callbacks by terminal id
+    outputs: Mutex<HashMap<String, OutputCallback>>,
+    /// Output for listed terminals not yet adopted
+    unclaimed: Mutex<HashMap<String, Vec<u8>>>,
+    /// Receives the ids of terminals whose shell exited
+    exits: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    /// Spawn requests awaiting an answer
+    spawns: Mutex<HashMap<String, SpawnAnswer>>,
+    /// List request awaiting an answer
+    list: Mutex<Option<oneshot::Sender<Vec<TerminalSnapshot>>>>,
+    /// The connection to the supervisor is gone
+    disconnected: AtomicBool,
+}
+
+/// Connection to the supervisor
+#[derive(Clone)]
+pub struct SupervisorClient {
+    tx: mpsc::UnboundedSender<Request>,
+    shared: Arc<Shared>,
+}
+
+impl SupervisorClient {
+    /// Connect to a running supervisor
+    pub async fn connect(socket_path: &Path) -> std::io::Result<Self> {
+        let stream = UnixStream::connect(socket_path).await?;
+        let (reader, mut writer) = stream.into_split();
+        let (tx, mut rx) = mpsc::unbounded_channel::<Request>();
+        let shared = Arc::new(Shared::default());
+
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                let Ok(mut line) = serde_json::to_vec(&request) else {
+                    continue;
+                };
+                line.push(b'\n');
+                if writer.write_all(&line).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let events = shared.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                match serde_json::from_str::<Event>(&line) {
+                    Ok(event) => events.dispatch(event),
+                    Err(e) => tracing::warn!("Invalid event from supervisor: {}", e),
+                }
+            }
+            tracing::error!(
+                "Lost connection to the supervisor; supervised terminals are unreachable"
+            );
+            events.disconnect();
+        });
+
+        Ok(Self { tx, shared })
+    }
+
+    /// Connect to the supervisor, starting it in the background if needed
+    ///
+    /// The supervisor is started in its own process group so it outlives
+    /// this process, logging to `log_path`.
+    pub async fn connect_or_spawn(socket_path: &Path, log_path: &Path) -> std::io::Result<Self> {
+        if let Ok(client) = Self::connect(socket_path).await {
+            tracing::info!("Connected to supervisor at {}", socket_path.display());
+            return Ok(client);
+        }
+
+        tracing::info!("Starting supervisor on {}", socket_path.display());
+        spawn_supervisor(socket_path, log_path)?;
+
+        let started = tokio::time::Instant::now();
+        loop {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            match Self::connect(socket_path).await {
+                Ok(client) => return Ok(client),
+                Err(e) if started.elapsed() > SPAWN_TIMEOUT => return Err(e),
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Start a shell under the supervisor
+    #[allow(clippy::too_many_arguments)]
+    pub async fn spawn<F>(
+        &self,
+        id: &str,
+        cols: u16,
+        rows: u16,
+        command: Option<String>,
+        cwd: Option<String>,
+        env: Vec<(String, String)>,
+        meta: serde_json::Value,
+        cgroup: Option<CgroupSpec>,
+        sandbox: Option<SandboxSpec>,
+        output_callback: F,
+    ) -> Result<Option<u32>, TerminalError>
+    where
+        F: Fn(Vec<u8>) + Send + 'static,
+    {
+        let (answer_tx, answer_rx) = oneshot::channel();
+        lock(&self.shared.spawns).insert(id.to_string(), answer_tx);
+        lock(&self.shared.outputs).insert(id.to_string(), Box::new(output_callback));
+
+        let sent = self.send(Request::Spawn {
+            id: id.to_string(),
+            cols,
+            rows,
+            command,
+            cwd,
+            env,
+            meta,
+            cgroup,
+            sandbox,
+        });
+        if let Err(e) = sent {
+            lock(&self.shared.spawns).remove(id);
+            lock(&self.shared.outputs).remove(id);
+            return Err(e);
+        }
+
+        let answer = answer_rx
+            .await
+            .unwrap_or_else(|_| Err("Supervisor disconnected".to_string()));
+        if answer.is_err() {
+            lock(&self.shared.outputs).remove(id);
+        }
+        answer.map_err(TerminalError::SupervisorError)
+    }
+
+    /// Route a listed terminal's output to a callback, replaying what arrived since
+    ///
+    /// Returns false if the terminal exited after it was listed.
+    pub fn adopt<F>(&self, id: &str, output_callback: F) -> bool
+    where
+        F: Fn(Vec<u8>) + Send + 'static,
+    {
+        let mut outputs = lock(&self.shared.outputs);
+        let Some(missed) = lock(&self.shared.unclaimed).remove(id) else {
+            return false;
+        };
+        if !missed.is_empty() {
+            output_callback(missed);
+        }
+        outputs.insert(id.to_string(), Box::new(output_callback));
+        true
+    }
+
+    /// Ids of spawned or adopted terminals whose shell exits from now on
+    pub fn exits(&self) -> mpsc::UnboundedReceiver<String> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        *lock(&self.shared.exits) = Some(tx);
+        rx
+    }
+
+    /// Input channel for a supervised terminal
+    pub fn input(&self, id: &str) -> mpsc::Sender<Vec<u8>> {
+        let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
+        let tx = self.tx.clone();
+        let id = id.to_string();
+        tokio::spawn(async move {
+            while let Some(data) = input_rx.recv().await {
+                let request = Request::Input {
+                    id: id.clone(),
+                    data,
+                };
+                if tx.send(request).is_err() {
+                    break;
+                }
+            }
+        });
+        input_tx
+    }
+
+    pub fn resize(&self, id: &str, cols: u16, rows: u16) -> Result<(), TerminalError> {
+        self.send(Request::Resize {
+            id: id.to_string(),
+            cols,
+            rows,
+        })
+    }
+
+    pub fn signal(
+        &self,
+        id: &str,
+        signal: Signal,
+        target: SignalTarget,
+    ) -> Result<(), TerminalError> {
+        self.send(Request::Signal {
+            id: id.to_string(),
+            signal,
+            target,
+        })
+    }
+
+    pub fn close(&self, id: &str) -> Result<(), TerminalError> {
+        lock(&self.shared.outputs).remove(id);
+        self.send(Request::Close { id: id.to_string() })
+    }
+
+    /// Replace the data `list` returns for a terminal
+    pub fn update_meta(&self, id: &str, meta: serde_json::Value) -> Result<(), TerminalError> {
+        self.send(Request::UpdateMeta {
+            id: id.to_string(),
+            meta,
+        })
+    }
+
+    /// Terminals running under the supervisor
+    pub async fn list(&self) -> Result<Vec<TerminalSnapshot>, TerminalError> {
+        let (answer_tx, answer_rx) = oneshot::channel();
+        *lock(&self.shared.list) = Some(answer_tx);
+        self.send(Request::List)?;
+        answer_rx
+            .await
+            .map_err(|_| TerminalError::SupervisorError("Supervisor disconnected".to_string()))
+    }
+
+    fn send(&self, request: Request) -> Result<(), TerminalError> {
+        let disconnected = || TerminalError::SupervisorError("Supervisor disconnected".to_string());
+        if self.shared.disconnected.load(Ordering::SeqCst) {
+            return Err(disconnected());
+        }
+        self.tx.send(request).map_err(|_| disconnected())
+    }
+}
+
+impl Shared {
+    /// Fail anything still waiting and report every terminal as exited
+    fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+        lock(&self.spawns).clear();
+        lock(&self.list).take();
+        lock(&self.unclaimed).clear();
+        let ids: Vec<String> = lock(&self.outputs).drain().map(|(id, _)| id).collect();
+        if let Some(exits) = &*lock(&self.exits) {
+            for id in ids {
+                let _ = exits.send(id);
+            }
+        }
+    }
+
+    fn dispatch(&self, event: Event) {
+        match event {
+            Event::Output { id, data } => {
+                let outputs = lock(&self.outputs);
+                match outputs.get(&id) {
+                    Some(callback) => callback(data),
+                    // Output of unknown terminals (e.g. already exited) is dropped
+                    None => {
+                        if let Some(missed) = lock(&self.unclaimed).get_mut(&id) {
+                            missed.extend(data);
+                        }
+                    }
+                }
+            }
+            Event::Exited { id } => {
+                let adopted = lock(&self.outputs).remove(&id).is_some();
+                lock(&self.unclaimed).remove(&id);
+                if adopted {
+                    if let Some(exits) = &*lock(&self.exits) {
+                        let _ = exits.send(id);
+                    }
+                }
+            }
+            Event::Spawned { id, pid } => {
+                if let Some(answer) = lock(&self.spawns).remove(&id) {
+                    let _ = answer.send(Ok(pid));
+                }
+            }
+            Event::Failed { id, message } => {
+                if let Some(answer) = lock(&self.spawns).remove(&id) {
+                    let _ = answer.send(Err(message));
+                }
+            }
+            Event::Terminals { terminals } => {
+                // Earlier output is part of the snapshots
+                *lock(&self.unclaimed) = terminals
+                    .iter()
+                    .map(|t| (t.id.clone(), Vec::new()))
+                    .collect();
+                if let Some(answer) = lock(&self.list).take() {
+                    let _ = answer.send(terminals);
+                }
+            }
+        }
+    }
+}
+
+/// Start `webshell supervisor` detached from this process
+fn spawn_supervisor(socket_path: &Path, log_path: &Path) -> std::io::Result<()> {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    if let Some(dir) = log_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+
+    Command::new(std::env::current_exe()?)
+        .arg("supervisor")
+        .arg("--supervisor-socket")
+        .arg(PathBuf::from(socket_path))
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // Keep Ctrl-C and the server's signals away from it
+        .process_group(0)
+        .spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_disconnect_exits_terminals() {
+        let dir = std::env::temp_dir().join(format!("webshell-client-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("supervisor.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+
+        // A supervisor that answers one spawn, then goes away
+        let supervisor = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let line = lines.next_line().await.unwrap().unwrap();
+            let Ok(Request::Spawn { id, .. }) = serde_json::from_str(&line) else {
+                panic!("not a spawn request: {}", line);
+            };
+            let mut event = serde_json::to_vec(&Event::Spawned { id, pid: Some(1) }).unwrap();
+            event.push(b'\n');
+            writer.write_all(&event).await.unwrap();
+        });
+
+        let client = SupervisorClient::connect(&socket).await.unwrap();
+        let mut exits = client.exits();
+        let spawned = client
+            .spawn(
+                "t1",
+                80,
+                24,
+                None,
+                None,
+                Vec::new(),
+                serde_json::Value::Null,
+                None,
+                None,
+                |_| {},
+            )
+            .await;
+        assert_eq!(spawned.unwrap(), Some(1));
+        supervisor.await.unwrap();
+
+        let exited = tokio::time::timeout(Duration::from_secs(10), exits.recv()).await;
+        assert_eq!(exited.unwrap().as_deref(), Some("t1"));
+        assert!(matches!(
+            client.resize("t1", 80, 24),
+            Err(TerminalError::SupervisorError(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,392 @@
+//! Supervisor process
+//!
+//! Owns the PTYs and keeps their scrollback while no server is connected.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, RwLock};
+
+use super::{Event, Request, TerminalSnapshot};
+use crate::terminal::cgroup::CgroupSpec;
+use crate::terminal::pty::{PtyManager, TerminalHandle};
+use crate::terminal::sandbox::SandboxSpec;
+use crate::terminal::scrollback::Scrollback;
+
+/// How often terminals are checked for exited shells
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Connected server, if any
+type ServerTx = Arc<Mutex<Option<mpsc::UnboundedSender<Event>>>>;
+
+/// Lock a mutex, recovering from poisoning
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A terminal owned by the supervisor
+struct Supervised {
+    handle: TerminalHandle,
+    cols: u16,
+    rows: u16,
+    meta: serde_json::Value,
+    scrollback: Arc<Mutex<Scrollback>>,
+}
+
+struct Supervisor {
+    pty_manager: PtyManager,
+    terminals: RwLock<HashMap<String, Supervised>>,
+    server: ServerTx,
+}
+
+/// Run the supervisor on a Unix socket until the process is killed
+pub async fn run(socket_path: &Path) -> std::io::Result<()> {
+    // Only the server's user may connect; terminals run as that user
+    let listener = crate::server::bind_unix(socket_path, Some(0o600), None)?;
+    tracing::info!("Supervisor listening on {}", socket_path.display());
+
+    let supervisor = Arc::new(Supervisor {
+        pty_manager: PtyManager::new(),
+        terminals: RwLock::new(HashMap::new()),
+        server: Arc::new(Mutex::new(None)),
+    });
+    tokio::spawn(supervisor.clone().reap());
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tracing::info!("Server connected");
+                tokio::spawn(supervisor.clone().serve(stream));
+            }
+            Err(e) => {
+                tracing::error!("Failed to accept server connection: {}", e);
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+impl Supervisor {
+    /// Drop terminals whose shell exited, telling the server
+    async fn reap(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let ids: Vec<String> = self.terminals.read().await.keys().cloned().collect();
+            for id in ids {
+                if !self.pty_manager.exited(&id).await {
+                    continue;
+                }
+                self.terminals.write().await.remove(&id);
+                if let Err(e) = self.pty_manager.close(&id).await {
+                    tracing::warn!("Failed to close terminal {}: {}", id, e);
+                }
+                tracing::info!("Terminal {} exited", id);
+                if let Some(server) = &*lock(&self.server) {
+                    let _ = server.send(Event::Exited { id });
+                }
+            }
+        }
+    }
+
+    /// Serve one server connection, replacing any previous one
+    async fn serve(self: Arc<Self>, stream: UnixStream) {
+        let (reader, mut writer) = stream.into_split();
+        let (tx, mut rx) = mpsc::unbounded_channel::<Event>();
+        *lock(&self.server) = Some(tx.clone());
+
+        let write_task = tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let Ok(mut line) = serde_json::to_vec(&event) else {
+                    continue;
+                };
+                line.push(b'\n');
+                if writer.write_all(&line).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match serde_json::from_str::<Request>(&line) {
+                Ok(request) => self.handle(request, &tx).await,
+                Err(e) => tracing::warn!("Invalid request from server: {}", e),
+            }
+        }
+
+        write_task.abort();
+        let mut server = lock(&self.server);
+        if server.as_ref().is_some_and(|s| s.same_channel(&tx)) {
+            *server = None;
+        }
+        tracing::info!("Server disconnected, terminals keep running");
+    }
+
+    /// Handle one request without waiting on any single terminal
+    async fn handle(self: &Arc<Self>, request: Request, tx: &mpsc::UnboundedSender<Event>) {
+        match request {
+            Request::Spawn {
+                id,
+                cols,
+                rows,
+                command,
+                cwd,
+                env,
+                meta,
+                cgroup,
+                sandbox,
+            } => {
+                // Joining a cgroup can take a while; other terminals carry on
+                let supervisor = self.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let event = supervisor
+                        .spawn(&id, cols, rows, command, cwd, env, meta, cgroup, sandbox)
+                        .await;
+                    let _ = tx.send(event);
+                });
+            }
+
+            Request::Input { id, data } => {
+                let input_tx = {
+                    let terminals = self.terminals.read().await;
+                    terminals.get(&id).map(|t| t.handle.input_tx.clone())
+                };
+                // A shell that stops reading loses input rather than stalling the rest
+                if let Some(Err(TrySendError::Full(_))) = input_tx.map(|tx| tx.try_send(data)) {
+                    tracing::warn!("Terminal {} is not reading its input, dropping it", id);
+                }
+            }
+
+            Request::Resize { id, cols, rows } => {
+                if let Err(e) = self.pty_manager.resize(&id, cols, rows).await {
+                    tracing::warn!("Failed to resize terminal {}: {}", id, e);
+                }
+                if let Some(terminal) = self.terminals.write().await.get_mut(&id) {
+                    terminal.cols = cols;
+                    terminal.rows = rows;
+                }
+            }
+
+            Request::Signal { id, signal, target } => {
+                if let Err(e) = self.pty_manager.signal(&id, signal, target).await {
+                    tracing::warn!("Failed to signal terminal {}: {}", id, e);
+                }
+            }
+
+            Request::Close { id } => {
+                if let Err(e) = self.pty_manager.close(&id).await {
+                    tracing::warn!("Failed to close terminal {}: {}", id, e);
+                }
+                self.terminals.write().await.remove(&id);
+            }
+
+            Request::UpdateMeta { id, meta } => {
+                if let Some(terminal) = self.terminals.write().await.get_mut(&id) {
+                    terminal.meta = meta;
+                }
+            }
+
+            Request::List => {
+                let terminals = self.terminals.read().await;
+                // Hold every scrollback lock until the snapshot is queued, so
+                // output is either in a snapshot or sent after it, never both
+                let locked: Vec<_> = terminals
+                    .iter()
+                    .map(|(id, t)| (id, t, lock(&t.scrollback)))
+                    .collect();
+                let terminals = locked
+                    .iter()
+                    .map(|(id, t, scrollback)| TerminalSnapshot {
+                        id: id.to_string(),
+                        pid: t.handle.pid,
+                        cols: t.cols,
+                        rows: t.rows,
+                        meta: t.meta.clone(),
+                        scrollback: scrollback.contents(),
+                    })
+                    .collect();
+                let _ = tx.send(Event::Terminals { terminals });
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn spawn(
+        &self,
+        id: &str,
+        cols: u16,
+        rows: u16,
+        command: Option<String>,
+        cwd: Option<String>,
+        env: Vec<(String, String)>,
+        meta: serde_json::Value,
+        cgroup: Option<CgroupSpec>,
+        sandbox: Option<SandboxSpec>,
+    ) -> Event {
+        // Output goes to the scrollback and whichever server is connected
+        let scrollback = Arc::new(Mutex::new(Scrollback::default()));
+        let output_scrollback = scrollback.clone();
+        let server = self.server.clone();
+        let output_id = id.to_string();
+        let callback = move |data: Vec<u8>| {
+            let mut scrollback = lock(&output_scrollback);
+            scrollback.push(&data);
+            if let Some(server) = &*lock(&server) {
+                let _ = server.send(Event::Output {
+                    id: output_id.clone(),
+                    data,
+                });
+            }
+        };
+
+        match self
+            .pty_manager
+            .spawn_pty(
+                id.to_string(),
+                cols,
+                rows,
+                command,
+                cwd,
+                env,
+                cgroup,
+                sandbox,
+                callback,
+            )
+            .await
+        {
+            Ok(handle) => {
+                let pid = handle.pid;
+                tracing::info!("Spawned terminal {} (pid {:?})", id, pid);
+                self.terminals.write().await.insert(
+                    id.to_string(),
+                    Supervised {
+                        handle,
+                        cols,
+                        rows,
+                        meta,
+                        scrollback,
+                    },
+                );
+                Event::Spawned {
+                    id: id.to_string(),
+                    pid,
+                }
+            }
+            Err(e) => Event::Failed {
+                id: id.to_string(),
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supervisor::SupervisorClient;
+    use tokio::time::timeout;
+
+    type Output = Arc<Mutex<Vec<u8>>>;
+
+    /// Collects a terminal's output
+    fn collector() -> (Output, impl Fn(Vec<u8>) + Send + 'static) {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let sink = output.clone();
+        (output, move |data: Vec<u8>| lock(&sink).extend(data))
+    }
+
+    async fn wait_for_output(output: &Mutex<Vec<u8>>, expected: &str) {
+        let found = timeout(Duration::from_secs(10), async {
+            while !String::from_utf8_lossy(&lock(output)).contains(expected) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        assert!(found.await.is_ok(), "no {:?} in the output", expected);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_list_and_exit() {
+        let dir = std::env::temp_dir().join(format!("webshell-supervisor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("supervisor.sock");
+        let daemon = tokio::spawn({
+            let socket = socket.clone();
+            async move { run(&socket).await }
+        });
+        let connect = || async {
+            for _ in 0..50 {
+                if let Ok(client) = SupervisorClient::connect(&socket).await {
+                    return client;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            panic!("supervisor did not start");
+        };
+
+        let client = connect().await;
+        let mut exits = client.exits();
+        let spawn = |id: &'static str, command: &str, callback| {
+            client.spawn(
+                id,
+                80,
+                24,
+                Some(command.to_string()),
+                None,
+                Vec::new(),
+                serde_json::json!({ "owner": "alice" }),
+                None,
+                None,
+                callback,
+            )
+        };
+
+        let (output, callback) = collector();
+        let pid = spawn("kept", "sh -c 'echo hi; exec sleep 60'", callback).await;
+        assert!(pid.unwrap().is_some());
+        wait_for_output(&output, "hi").await;
+
+        let (output, callback) = collector();
+        spawn("done", "sh -c 'echo bye'", callback).await.unwrap();
+        wait_for_output(&output, "bye").await;
+        let exited = timeout(Duration::from_secs(10), exits.recv()).await;
+        assert_eq!(exited.unwrap().as_deref(), Some("done"));
+
+        // A new connection finds the running terminal with its scrollback
+        drop(client);
+        let client = connect().await;
+        let terminals = client.list().await.unwrap();
+        assert_eq!(terminals.len(), 1);
+        let kept = &terminals[0];
+        assert_eq!(kept.id, "kept");
+        assert_eq!((kept.cols, kept.rows), (80, 24));
+        assert_eq!(kept.meta["owner"], "alice");
+        assert!(kept.scrollback.contains("hi"));
+
+        client
+            .update_meta(
+                "kept",
+                serde_json::json!({ "owner": "alice", "name": "build" }),
+            )
+            .unwrap();
+        let terminals = client.list().await.unwrap();
+        assert_eq!(terminals[0].meta["name"], "build");
+
+        client.close("kept").unwrap();
+        let closed = timeout(Duration::from_secs(10), async {
+            while !client.list().await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        assert!(closed.await.is_ok());
+
+        daemon.abort();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
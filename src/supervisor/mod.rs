@@ -0,0 +1,189 @@
+//! Terminal supervisor
+//!
+//! An optional separate process (`webshell supervisor`) that owns local PTYs
+//! so terminals outlive the web server. The server talks to it over a Unix
+//! socket with newline-delimited JSON, and after a restart lists the
+//! surviving terminals and reattaches them with their scrollback. Terminal
+//! input and output travel as base64, so any bytes pass through unchanged.
+//!
+//! Only one server connection is served at a time; a new one replaces it.
+
+pub mod client;
+pub mod daemon;
+
+use serde::{Deserialize, Serialize};
+
+pub use client::SupervisorClient;
+
+use crate::terminal::cgroup::CgroupSpec;
+use crate::terminal::sandbox::SandboxSpec;
+use crate::types::{Signal, SignalTarget};
+
+/// Server-to-supervisor request
+// Requests are sent one at a time, so a large `Spawn` costs nothing
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Start a shell; answered with `Spawned` or `Failed`
+    Spawn {
+        id: String,
+        cols: u16,
+        rows: u16,
+        command: Option<String>,
+        cwd: Option<String>,
+        env: Vec<(String, String)>,
+        /// Opaque server data returned by `List`
+        meta: serde_json::Value,
+        #[serde(default)]
+        cgroup: Option<CgroupSpec>,
+        #[serde(default)]
+        sandbox: Option<SandboxSpec>,
+    },
+    Input {
+        id: String,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    Resize {
+        id: String,
+        cols: u16,
+        rows: u16,
+    },
+    Signal {
+        id: String,
+        signal: Signal,
+        target: SignalTarget,
+    },
+    Close {
+        id: String,
+    },
+    /// Replace the server data kept with a terminal
+    UpdateMeta {
+        id: String,
+        meta: serde_json::Value,
+    },
+    /// Answered with `Terminals`
+    List,
+}
+
+/// Supervisor-to-server event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Spawned {
+        id: String,
+        pid: Option<u32>,
+    },
+    Failed {
+        id: String,
+        message: String,
+    },
+    Output {
+        id: String,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    /// The shell exited on its own; the terminal is gone
+    Exited {
+        id: String,
+    },
+    /// Every running terminal; output after this event is not in the snapshots
+    Terminals {
+        terminals: Vec<TerminalSnapshot>,
+    },
+}
+
+/// A running terminal as reported by `List`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalSnapshot {
+    pub id: String,
+    pub pid: Option<u32>,
+    pub cols: u16,
+    pub rows: u16,
+    pub meta: serde_json::Value,
+    pub scrollback: String,
+}
+
+/// Bytes as a base64 string
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_round_trip() {
+        let request = Request::Input {
+            id: "t1".to_string(),
+            data: vec![0x1b, b'[', 0xff, 0xc3],
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(json, r#"{"type":"input","id":"t1","data":"G1v/ww=="}"#);
+        let Request::Input { id, data } = serde_json::from_str(&json).unwrap() else {
+            panic!("not an input request");
+        };
+        assert_eq!((id.as_str(), data), ("t1", vec![0x1b, b'[', 0xff, 0xc3]));
+
+        let request = Request::Spawn {
+            id: "t2".to_string(),
+            cols: 80,
+            rows: 24,
+            command: Some("top".to_string()),
+            cwd: None,
+            env: vec![("LANG".to_string(), "C.UTF-8".to_string())],
+            meta: serde_json::json!({ "owner": "alice" }),
+            cgroup: None,
+            sandbox: None,
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        let Request::Spawn {
+            command, env, meta, ..
+        } = serde_json::from_str(&json).unwrap()
+        else {
+            panic!("not a spawn request");
+        };
+        assert_eq!(command.as_deref(), Some("top"));
+        assert_eq!(env, [("LANG".to_string(), "C.UTF-8".to_string())]);
+        assert_eq!(meta["owner"], "alice");
+
+        // A UTF-8 sequence split across two chunks survives
+        for data in [vec![b'a', 0xe2, 0x82], vec![0xac]] {
+            let event = Event::Output {
+                id: "t1".to_string(),
+                data: data.clone(),
+            };
+            let json = serde_json::to_string(&event).unwrap();
+            let Event::Output { data: decoded, .. } = serde_json::from_str(&json).unwrap() else {
+                panic!("not an output event");
+            };
+            assert_eq!(decoded, data);
+        }
+
+        let json = serde_json::to_string(&Event::Exited {
+            id: "t1".to_string(),
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"type":"exited","id":"t1"}"#);
+        assert!(matches!(
+            serde_json::from_str(&json).unwrap(),
+            Event::Exited { id } if id == "t1"
+        ));
+        assert!(
+            serde_json::from_str::<Event>(r#"{"type":"output","id":"t1","data":"%"}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! cgroup v2 resource limits
+//!
+//! With `cgroup_root` set, every local shell is started in a child cgroup of
+//! it, one per terminal or one per user, carrying the configured CPU, memory
+//! and pids limits. The root must be a cgroup v2 directory the server may
+//! write to, e.g. one delegated by systemd (`Delegate=yes`).
+//!
+//! Shells are started through `webshell join-cgroup`, which joins the cgroup
+//! before running them, so nothing they start escapes the limits.
+
+use nix::unistd::execvp;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cli::JoinCgroupArgs;
+use crate::config::{CgroupScope, Config};
+
+/// CPU bandwidth period (microseconds)
+const CPU_PERIOD: u64 = 100_000;
+
+/// A cgroup for a shell and the limits to give it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CgroupSpec {
+    pub path: PathBuf,
+    /// Used by several terminals (per-user scope)
+    pub shared: bool,
+    /// Cores
+    pub cpu: Option<f64>,
+    pub memory_mb: Option<u64>,
+    pub pids: Option<u32>,
+}
+
+impl CgroupSpec {
+    /// The cgroup a new terminal's shell goes in, if cgroups are enabled
+    pub fn for_terminal(config: &Config, username: &str, terminal_id: &str) -> Option<Self> {
+        let root = Path::new(config.cgroup_root.as_deref()?);
+        let name = match config.cgroup_scope {
+            CgroupScope::Terminal => format!("terminal-{}", encode(terminal_id)),
+            CgroupScope::User => format!("user-{}", encode(username)),
+        };
+        Some(Self {
+            path: root.join(name),
+            shared: config.cgroup_scope == CgroupScope::User,
+            cpu: config.cgroup_cpu,
+            memory_mb: config.cgroup_memory_mb,
+            pids: config.cgroup_pids,
+        })
+    }
+
+    /// Create the cgroup (if needed) and apply the limits
+    pub fn create(&self) -> io::Result<()> {
+        let root = self.path.parent().unwrap_or(Path::new("/"));
+        let controllers: Vec<&str> = [
+            ("+cpu", self.cpu.is_some()),
+            ("+memory", self.memory_mb.is_some()),
+            ("+pids", self.pids.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(controller, used)| used.then_some(controller))
+        .collect();
+        if !controllers.is_empty() {
+            write(&root.join("cgroup.subtree_control"), &controllers.join(" "))?;
+        }
+
+        match fs::create_dir(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                return Err(context(&self.path, e))
+            }
+            _ => {}
+        }
+        if let Some(cpu) = self.cpu {
+            let quota = (cpu * CPU_PERIOD as f64).round() as u64;
+            write(
+                &self.path.join("cpu.max"),
+                &format!("{} {}", quota, CPU_PERIOD),
+            )?;
+        }
+        if let Some(memory_mb) = self.memory_mb {
+            write(
+                &self.path.join("memory.max"),
+                &(memory_mb * 1024 * 1024).to_string(),
+            )?;
+        }
+        if let Some(pids) = self.pids {
+            write(&self.path.join("pids.max"), &pids.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Arguments to `webshell` that run `program` in this cgroup
+    pub fn args(&self, program: &str, args: &[String]) -> Vec<OsString> {
+        let mut argv: Vec<OsString> = vec![
+            "join-cgroup".into(),
+            "--cgroup".into(),
+            self.path.clone().into(),
+            "--".into(),
+            program.into(),
+        ];
+        argv.extend(args.iter().map(OsString::from));
+        argv
+    }
+
+    /// Check if a process is in the cgroup
+    pub fn contains(&self, pid: u32) -> io::Result<bool> {
+        let procs = fs::read_to_string(self.path.join("cgroup.procs"))
+            .map_err(|e| context(&self.path, e))?;
+        Ok(procs.lines().any(|line| line.parse() == Ok(pid)))
+    }
+
+    /// Try to remove the cgroup
+    ///
+    /// A terminal's own cgroup has whatever is left in it killed first; a
+    /// shared one is only removed once empty. Fails with `ResourceBusy`
+    /// while processes are still exiting or (shared) still running.
+    pub fn remove(&self) -> io::Result<()> {
+        if !self.shared {
+            // Kernels before 5.14 lack cgroup.kill; leftovers then keep it busy
+            let _ = fs::write(self.path.join("cgroup.kill"), "1");
+        }
+        match fs::remove_dir(&self.path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Move this process into a cgroup
+pub fn join(path: &Path) -> io::Result<()> {
+    write(&path.join("cgroup.procs"), &std::process::id().to_string())
+}
+
+/// Run `webshell join-cgroup`: join the cgroup, then become the command
+///
+/// Exits with 126, like a shell, if either step fails.
+pub fn run(args: &JoinCgroupArgs) -> ! {
+    let Err(e) = exec(args);
+    eprintln!("webshell join-cgroup: {:#}", e);
+    std::process::exit(126)
+}
+
+fn exec(args: &JoinCgroupArgs) -> anyhow::Result<Infallible> {
+    join(&args.cgroup)?;
+    let argv = args
+        .command
+        .iter()
+        .map(|arg| CString::new(arg.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(execvp(&argv[0], &argv)?)
+}
+
+/// Processes killed for running out of memory in a cgroup so far
+pub fn oom_kills(path: &Path) -> Option<u64> {
+    let events = fs::read_to_string(path.join("memory.events")).ok()?;
+    parse_oom_kills(&events)
+}
+
+fn parse_oom_kills(events: &str) -> Option<u64> {
+    events
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill ")?.trim().parse().ok())
+}
+
+/// Write a cgroup control file, naming it in errors
+fn write(path: &Path, value: &str) -> io::Result<()> {
+    fs::write(path, value).map_err(|e| context(path, e))
+}
+
+fn context(path: &Path, error: io::Error) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
+}
+
+/// Encode a name into cgroup-safe characters (letters, digits, `-`, `_xx` escapes)
+fn encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+
+    #[test]
+    fn test_for_terminal() {
+        assert!(CgroupSpec::for_terminal(&Config::default(), "alice", "t1").is_none());
+
+        let config = Config::from_settings(Settings {
+            cgroup_root: Some("/sys/fs/cgroup/webshell".to_string()),
+            cgroup_scope: Some("user".to_string()),
+            cgroup_memory_mb: Some(512),
+            ..Settings::default()
+        })
+        .unwrap();
+        let spec = CgroupSpec::for_terminal(&config, "a.b/c", "t1").unwrap();
+        assert_eq!(
+            spec.path,
+            PathBuf::from("/sys/fs/cgroup/webshell/user-a_2eb_2fc")
+        );
+        assert!(spec.shared);
+        assert_eq!(spec.memory_mb, Some(512));
+    }
+
+    #[test]
+    fn test_args() {
+        use crate::cli::{Cli, Command};
+        use clap::Parser;
+
+        let spec = CgroupSpec {
+            path: PathBuf::from("/sys/fs/cgroup/webshell/terminal-t1"),
+            shared: false,
+            cpu: None,
+            memory_mb: None,
+            pids: Some(64),
+        };
+        let argv = spec.args("/bin/bash", &["--login".to_string()]);
+
+        let cli = Cli::parse_from(std::iter::once(OsString::from("webshell")).chain(argv));
+        let Some(Command::JoinCgroup(args)) = cli.command else {
+            panic!("not a join-cgroup command");
+        };
+        assert_eq!(args.cgroup, spec.path);
+        assert_eq!(args.command, ["/bin/bash", "--login"]);
+    }
+
+    #[test]
+    fn test_parse_oom_kills() {
+        let events = "low 0\nhigh 0\nmax 12\noom 3\noom_kill 2\noom_group_kill 0\n";
+        assert_eq!(parse_oom_kills(events), Some(2));
+        assert_eq!(parse_oom_kills(""), None);
+    }
+}
@@ -2,6 +2,8 @@
 
 use thiserror::Error;
 
+use crate::types::ErrorCode;
+
 #[derive(Debug, Error)]
 pub enum TerminalError {
     #[error("Terminal not found: {0}")]
@@ -16,9 +18,48 @@ pub enum TerminalError {
     #[error("Send error: {0}")]
     SendError(String),
 
+    #[error("{0}")]
+    SshError(String),
+
+    #[error("Supervisor error: {0}")]
+    SupervisorError(String),
+
+    #[error("Cgroup error: {0}")]
+    CgroupError(String),
+
+    #[error("Access denied to terminal: {0}")]
+    AccessDenied(String),
+
+    #[error("Share link has reached its viewer limit: {0}")]
+    ViewerLimit(String),
+
+    #[error("Terminal is read-only for this client: {0}")]
+    ReadOnly(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
     #[error("Maximum terminals reached")]
     MaxTerminalsReached,
 
     #[error("Anyhow error: {0}")]
     AnyhowError(#[from] anyhow::Error),
 }
+
+impl TerminalError {
+    /// Protocol error code reported to clients
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::AlreadyExists(_) => ErrorCode::AlreadyExists,
+            Self::PtyError(_) | Self::SendError(_) | Self::CgroupError(_) => ErrorCode::SpawnFailed,
+            Self::SshError(_) => ErrorCode::SshFailed,
+            Self::SupervisorError(_) => ErrorCode::SupervisorFailed,
+            Self::AccessDenied(_) | Self::ViewerLimit(_) => ErrorCode::AccessDenied,
+            Self::ReadOnly(_) => ErrorCode::ReadOnly,
+            Self::InvalidRequest(_) => ErrorCode::BadRequest,
+            Self::MaxTerminalsReached => ErrorCode::MaxTerminals,
+            Self::AnyhowError(_) => ErrorCode::Internal,
+        }
+    }
+}
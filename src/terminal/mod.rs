@@ -2,8 +2,14 @@
 //!
 //! Provides terminal emulation with PTY support.
 
+pub mod cgroup;
 pub mod error;
+pub mod process;
 pub mod pty;
+pub mod sandbox;
+pub mod scrollback;
 pub mod session;
+pub mod tmux;
+pub mod usage;
 
-pub use session::SessionManager;
+pub use session::{Client, SessionManager};
@@ -0,0 +1,97 @@
+//! Foreground process tracking
+//!
+//! What a local terminal is doing is read from `/proc`: the foreground
+//! process group of its PTY (from `tcgetpgrp` on the master, or the shell's
+//! `tpgid` when the master lives in the supervisor), that group leader's name
+//! and command line, and its working directory.
+
+use crate::types::{ForegroundProcess, ProcessStatus};
+
+/// Foreground process group of a process's controlling terminal
+pub fn foreground_group(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_tpgid(&stat)
+}
+
+/// The `tpgid` field of `/proc/<pid>/stat`
+///
+/// The command name may itself contain spaces and parentheses, so fields are
+/// counted from the last `)`.
+fn parse_tpgid(stat: &str) -> Option<u32> {
+    let fields = &stat[stat.rfind(')')? + 1..];
+    // state ppid pgrp session tty_nr tpgid
+    let tpgid: i64 = fields.split_whitespace().nth(5)?.parse().ok()?;
+    u32::try_from(tpgid).ok().filter(|&pgid| pgid > 0)
+}
+
+/// The shell of a sandboxed terminal, given the sandbox's pid
+///
+/// The sandbox runs an init process, which runs the shell.
+pub fn sandboxed_shell(sandbox_pid: u32) -> Option<u32> {
+    first_child(sandbox_pid).and_then(first_child)
+}
+
+fn first_child(pid: u32) -> Option<u32> {
+    let children = std::fs::read_to_string(format!("/proc/{0}/task/{0}/children", pid)).ok()?;
+    children.split_whitespace().next()?.parse().ok()
+}
+
+/// What a shell is running, given its foreground process group
+///
+/// Falls back to the shell's own directory if the foreground process is gone.
+pub fn status(shell_pid: u32, foreground: Option<u32>) -> ProcessStatus {
+    let process = foreground.and_then(read_process);
+    let cwd = process
+        .as_ref()
+        .and_then(|p| read_cwd(p.pid))
+        .or_else(|| read_cwd(shell_pid));
+    ProcessStatus {
+        busy: process.as_ref().is_some_and(|p| p.pid != shell_pid),
+        process,
+        cwd,
+    }
+}
+
+fn read_process(pid: u32) -> Option<ForegroundProcess> {
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    Some(ForegroundProcess {
+        pid,
+        name: name.trim_end().to_string(),
+        cmdline: parse_cmdline(&cmdline),
+    })
+}
+
+/// Arguments of `/proc/<pid>/cmdline` (NUL-separated), joined with spaces
+fn parse_cmdline(raw: &[u8]) -> String {
+    raw.split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn read_cwd(pid: u32) -> Option<String> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid))
+        .ok()
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tpgid() {
+        assert_eq!(
+            parse_tpgid("4242 (bash) S 4241 4242 4242 34816 4300 4194560 0"),
+            Some(4300)
+        );
+        // A command name with spaces and parentheses
+        assert_eq!(parse_tpgid("7 (a) b (c) R 1 7 7 34817 7 0 0"), Some(7));
+        // No controlling terminal
+        assert_eq!(parse_tpgid("1 (init) S 0 1 1 0 -1 4194560 0"), None);
+
+        assert_eq!(parse_cmdline(b"vim\0-R\0notes.txt\0"), "vim -R notes.txt");
+    }
+}
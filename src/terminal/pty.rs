@@ -1,30 +1,92 @@
 //! PTY (Pseudo-Terminal) Manager
 //!
-//! Handles terminal process lifecycle using portable-pty for cross-platform support.
+//! Handles terminal process lifecycle using portable-pty for cross-platform support,
+//! and remote shells over SSH behind the same interface.
 
+use nix::sys::signal::{kill, killpg, Signal as NixSignal};
+use nix::unistd::Pid;
 use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use russh::Sig;
 use std::collections::HashMap;
 use std::io::{Read, Write};
-use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex, RwLock};
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError};
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex, OwnedMutexGuard, RwLock};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 
+use super::cgroup::CgroupSpec;
 use super::error::TerminalError;
+use super::process;
+use super::sandbox::SandboxSpec;
+use crate::ssh::{self, ShellOptions, SshConfig, SshShell};
+use crate::supervisor::SupervisorClient;
+use crate::types::{Signal, SignalTarget};
+
+/// How long a closed shell has to exit after SIGHUP before it is killed
+const CLOSE_GRACE: Duration = Duration::from_secs(5);
+
+/// How long a new shell has to join its cgroup
+const JOIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Handle for interacting with a terminal
 #[derive(Clone)]
 pub struct TerminalHandle {
     pub input_tx: mpsc::Sender<Vec<u8>>,
+    /// Process id of the shell, if known
+    pub pid: Option<u32>,
+    /// Survives a server restart (runs under the supervisor)
+    pub persistent: bool,
 }
 
 /// Internal terminal state
-struct TerminalState {
-    master: Box<dyn MasterPty + Send>,
-    child: Box<dyn Child + Send + Sync>,
+enum TerminalState {
+    /// Local PTY and its child process
+    Pty {
+        master: Box<dyn MasterPty + Send>,
+        child: Box<dyn Child + Send + Sync>,
+        /// Removed once the shell has exited
+        cgroup: Option<CgroupSpec>,
+    },
+    /// Remote shell
+    Ssh(SshShell),
+    /// Local PTY owned by the supervisor process
+    Supervised { pid: Option<u32> },
+}
+
+/// Locks serializing setup and removal of shared cgroups, one per cgroup
+///
+/// Without them one terminal could remove a per-user cgroup between another
+/// creating it and its shell joining.
+#[derive(Clone, Default)]
+struct CgroupLocks(Arc<std::sync::Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>>);
+
+impl CgroupLocks {
+    /// Lock a shared cgroup; a terminal's own needs no lock
+    async fn lock(&self, cgroup: &CgroupSpec) -> Option<OwnedMutexGuard<()>> {
+        if !cgroup.shared {
+            return None;
+        }
+        let lock = self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(cgroup.path.clone())
+            .or_default()
+            .clone();
+        Some(lock.lock_owned().await)
+    }
 }
 
 /// Manages PTY terminal instances
 pub struct PtyManager {
     terminals: Arc<RwLock<HashMap<String, Arc<Mutex<TerminalState>>>>>,
+    /// Runs local shells out of process, if configured
+    supervisor: Option<SupervisorClient>,
+    /// Closed shells not yet exited
+    closing: Mutex<JoinSet<()>>,
+    cgroup_locks: CgroupLocks,
 }
 
 impl Default for PtyManager {
@@ -37,28 +99,151 @@ impl PtyManager {
     pub fn new() -> Self {
         Self {
             terminals: Arc::new(RwLock::new(HashMap::new())),
+            supervisor: None,
+            closing: Mutex::new(JoinSet::new()),
+            cgroup_locks: CgroupLocks::default(),
+        }
+    }
+
+    /// Run local shells under a supervisor so they survive restarts
+    pub fn with_supervisor(supervisor: SupervisorClient) -> Self {
+        Self {
+            supervisor: Some(supervisor),
+            ..Self::new()
+        }
+    }
+
+    /// Fail if a terminal id is taken
+    async fn check_unused(&self, terminal_id: &str) -> Result<(), TerminalError> {
+        let terminals = self.terminals.read().await;
+        if terminals.contains_key(terminal_id) {
+            return Err(TerminalError::AlreadyExists(terminal_id.to_string()));
         }
+        Ok(())
     }
 
     /// Spawn a new terminal
+    ///
+    /// Runs a login shell, or `command` through the shell if given. With a
+    /// supervisor the shell runs there, and `meta` is kept alongside it for
+    /// [`SupervisorClient::list`]; otherwise `meta` is ignored. The shell
+    /// starts in `cgroup` and runs in `sandbox`, if given.
+    #[allow(clippy::too_many_arguments)]
     pub async fn spawn<F>(
         &self,
         terminal_id: String,
         cols: u16,
         rows: u16,
+        command: Option<String>,
         cwd: Option<String>,
         env: Vec<(String, String)>,
+        meta: serde_json::Value,
+        cgroup: Option<CgroupSpec>,
+        sandbox: Option<SandboxSpec>,
         output_callback: F,
     ) -> Result<TerminalHandle, TerminalError>
     where
         F: Fn(Vec<u8>) + Send + 'static,
     {
-        // Check if terminal already exists
-        {
-            let terminals = self.terminals.read().await;
-            if terminals.contains_key(&terminal_id) {
-                return Err(TerminalError::AlreadyExists(terminal_id));
-            }
+        let Some(supervisor) = &self.supervisor else {
+            return self
+                .spawn_pty(
+                    terminal_id,
+                    cols,
+                    rows,
+                    command,
+                    cwd,
+                    env,
+                    cgroup,
+                    sandbox,
+                    output_callback,
+                )
+                .await;
+        };
+        self.check_unused(&terminal_id).await?;
+
+        let pid = supervisor
+            .spawn(
+                &terminal_id,
+                cols,
+                rows,
+                command,
+                cwd,
+                env,
+                meta,
+                cgroup,
+                sandbox,
+                output_callback,
+            )
+            .await?;
+        Ok(self.insert_supervised(terminal_id, pid).await)
+    }
+
+    /// Take over a terminal left running under the supervisor
+    pub async fn adopt<F>(
+        &self,
+        terminal_id: String,
+        pid: Option<u32>,
+        output_callback: F,
+    ) -> Result<TerminalHandle, TerminalError>
+    where
+        F: Fn(Vec<u8>) + Send + 'static,
+    {
+        let supervisor = self
+            .supervisor
+            .as_ref()
+            .ok_or_else(|| TerminalError::SupervisorError("No supervisor".to_string()))?;
+        self.check_unused(&terminal_id).await?;
+
+        if !supervisor.adopt(&terminal_id, output_callback) {
+            return Err(TerminalError::NotFound(terminal_id));
+        }
+        Ok(self.insert_supervised(terminal_id, pid).await)
+    }
+
+    async fn insert_supervised(&self, terminal_id: String, pid: Option<u32>) -> TerminalHandle {
+        let supervisor = self.supervisor.as_ref().expect("supervised terminal");
+        let input_tx = supervisor.input(&terminal_id);
+        self.terminals.write().await.insert(
+            terminal_id,
+            Arc::new(Mutex::new(TerminalState::Supervised { pid })),
+        );
+
+        TerminalHandle {
+            input_tx,
+            pid,
+            persistent: true,
+        }
+    }
+
+    /// Spawn a terminal in this process
+    #[allow(clippy::too_many_arguments)]
+    pub async fn spawn_pty<F>(
+        &self,
+        terminal_id: String,
+        cols: u16,
+        rows: u16,
+        command: Option<String>,
+        cwd: Option<String>,
+        env: Vec<(String, String)>,
+        cgroup: Option<CgroupSpec>,
+        sandbox: Option<SandboxSpec>,
+        output_callback: F,
+    ) -> Result<TerminalHandle, TerminalError>
+    where
+        F: Fn(Vec<u8>) + Send + 'static,
+    {
+        self.check_unused(&terminal_id).await?;
+
+        // Held until the shell is in its cgroup
+        let cgroup_guard = match &cgroup {
+            Some(cgroup) => self.cgroup_locks.lock(cgroup).await,
+            None => None,
+        };
+        if let Some(cgroup) = &cgroup {
+            cgroup
+                .create()
+                .map_err(|e| TerminalError::CgroupError(e.to_string()))?;
         }
 
         let pty_system = native_pty_system();
@@ -71,17 +256,47 @@ impl PtyManager {
         })?;
 
         // Build command
-        let mut cmd = CommandBuilder::new(get_default_shell());
+        let shell = get_default_shell();
+        let mut args = Vec::new();
 
         // Add login shell arguments
         #[cfg(unix)]
-        cmd.arg("--login");
+        args.push("--login".to_string());
 
-        // Set working directory
-        if let Some(dir) = cwd {
-            cmd.cwd(dir);
+        if let Some(command) = command {
+            args.push("-c".to_string());
+            args.push(command);
         }
 
+        let mut cmd = match &sandbox {
+            // The sandbox picks its own working directory
+            Some(sandbox) => sandbox.command(
+                std::env::current_exe()?,
+                cgroup.as_ref().map(|c| c.path.as_path()),
+                shell,
+                &args,
+            ),
+            // Joins the cgroup before exec, so nothing the shell starts escapes it
+            None => {
+                let mut cmd = match &cgroup {
+                    Some(cgroup) => {
+                        let mut cmd = CommandBuilder::new(std::env::current_exe()?);
+                        cmd.args(cgroup.args(shell, &args));
+                        cmd
+                    }
+                    None => {
+                        let mut cmd = CommandBuilder::new(shell);
+                        cmd.args(&args);
+                        cmd
+                    }
+                };
+                if let Some(dir) = cwd {
+                    cmd.cwd(dir);
+                }
+                cmd
+            }
+        };
+
         // Set environment variables
         for (key, value) in env {
             cmd.env(key, value);
@@ -91,7 +306,24 @@ impl PtyManager {
         cmd.env("TERM", "xterm-256color");
 
         // Spawn child process
-        let child = pair.slave.spawn_command(cmd)?;
+        let mut child = match pair.slave.spawn_command(cmd) {
+            Ok(child) => child,
+            Err(e) => {
+                discard_cgroup(cgroup.as_ref());
+                return Err(e.into());
+            }
+        };
+        let pid = child.process_id();
+
+        if let Some(cgroup) = &cgroup {
+            if let Err(e) = wait_joined(&mut *child, cgroup).await {
+                let _ = child.kill();
+                reap(child).await;
+                discard_cgroup(Some(cgroup));
+                return Err(e);
+            }
+        }
+        drop(cgroup_guard);
 
         // Get master for I/O
         let master = pair.master;
@@ -137,14 +369,52 @@ impl PtyManager {
         });
 
         // Store terminal state
-        let terminal_state = TerminalState { master, child };
+        let terminal_state = TerminalState::Pty {
+            master,
+            child,
+            cgroup,
+        };
 
         self.terminals
             .write()
             .await
             .insert(terminal_id.clone(), Arc::new(Mutex::new(terminal_state)));
 
-        Ok(TerminalHandle { input_tx })
+        Ok(TerminalHandle {
+            input_tx,
+            pid,
+            persistent: false,
+        })
+    }
+
+    /// Open a remote shell over SSH
+    pub async fn spawn_ssh<F>(
+        &self,
+        terminal_id: String,
+        config: SshConfig,
+        options: ShellOptions,
+        output_callback: F,
+    ) -> Result<TerminalHandle, TerminalError>
+    where
+        F: Fn(Vec<u8>) + Send + 'static,
+    {
+        self.check_unused(&terminal_id).await?;
+
+        let shell = ssh::open_shell(config, options, output_callback)
+            .await
+            .map_err(TerminalError::SshError)?;
+        let input_tx = shell.input_tx.clone();
+
+        self.terminals
+            .write()
+            .await
+            .insert(terminal_id, Arc::new(Mutex::new(TerminalState::Ssh(shell))));
+
+        Ok(TerminalHandle {
+            input_tx,
+            pid: None,
+            persistent: false,
+        })
     }
 
     /// Resize terminal
@@ -157,36 +427,304 @@ impl PtyManager {
         let terminals = self.terminals.read().await;
 
         if let Some(terminal) = terminals.get(terminal_id) {
-            let state = terminal.lock().await;
-            state.master.resize(PtySize {
-                rows,
-                cols,
-                pixel_width: 0,
-                pixel_height: 0,
-            })?;
+            match &*terminal.lock().await {
+                TerminalState::Pty { master, .. } => master.resize(PtySize {
+                    rows,
+                    cols,
+                    pixel_width: 0,
+                    pixel_height: 0,
+                })?,
+                TerminalState::Ssh(shell) => shell.resize(cols, rows),
+                TerminalState::Supervised { .. } => {
+                    if let Some(supervisor) = &self.supervisor {
+                        supervisor.resize(terminal_id, cols, rows)?;
+                    }
+                }
+            }
             Ok(())
         } else {
             Err(TerminalError::NotFound(terminal_id.to_string()))
         }
     }
 
+    /// Send a signal to a terminal's processes
+    ///
+    /// SSH terminals pass the signal to the server, which picks the process.
+    pub async fn signal(
+        &self,
+        terminal_id: &str,
+        signal: Signal,
+        target: SignalTarget,
+    ) -> Result<(), TerminalError> {
+        let terminals = self.terminals.read().await;
+        let terminal = terminals
+            .get(terminal_id)
+            .ok_or_else(|| TerminalError::NotFound(terminal_id.to_string()))?;
+
+        match &*terminal.lock().await {
+            TerminalState::Pty { master, child, .. } => {
+                let sent = match target {
+                    SignalTarget::Foreground => master
+                        .process_group_leader()
+                        .map(|pgid| killpg(Pid::from_raw(pgid), nix_signal(signal))),
+                    SignalTarget::Leader => child
+                        .process_id()
+                        .map(|pid| kill(Pid::from_raw(pid as i32), nix_signal(signal))),
+                };
+                match sent {
+                    Some(result) => result.map_err(|e| TerminalError::PtyError(e.into()))?,
+                    None => {
+                        return Err(TerminalError::SendError("No process to signal".to_string()))
+                    }
+                }
+            }
+            TerminalState::Ssh(shell) => shell.signal(ssh_signal(signal)),
+            TerminalState::Supervised { .. } => {
+                if let Some(supervisor) = &self.supervisor {
+                    supervisor.signal(terminal_id, signal, target)?;
+                }
+            }
+        }
+        tracing::info!(
+            "Sent {:?} to terminal {} ({:?})",
+            signal,
+            terminal_id,
+            target
+        );
+        Ok(())
+    }
+
+    /// Foreground process group of a local terminal
+    pub async fn foreground(&self, terminal_id: &str) -> Option<u32> {
+        let terminal = self.terminals.read().await.get(terminal_id)?.clone();
+        let state = terminal.lock().await;
+        match &*state {
+            TerminalState::Pty { master, .. } => master
+                .process_group_leader()
+                .and_then(|pgid| u32::try_from(pgid).ok()),
+            // The PTY master is in the supervisor, so ask /proc instead
+            TerminalState::Supervised { pid } => pid.and_then(process::foreground_group),
+            TerminalState::Ssh(_) => None,
+        }
+    }
+
+    /// Replace the data kept with a supervised terminal; others have none
+    pub async fn update_meta(
+        &self,
+        terminal_id: &str,
+        meta: serde_json::Value,
+    ) -> Result<(), TerminalError> {
+        let terminals = self.terminals.read().await;
+        let terminal = terminals
+            .get(terminal_id)
+            .ok_or_else(|| TerminalError::NotFound(terminal_id.to_string()))?;
+        if let (TerminalState::Supervised { .. }, Some(supervisor)) =
+            (&*terminal.lock().await, &self.supervisor)
+        {
+            supervisor.update_meta(terminal_id, meta)?;
+        }
+        Ok(())
+    }
+
+    /// Check if a local shell has exited
+    pub async fn exited(&self, terminal_id: &str) -> bool {
+        let Some(terminal) = self.terminals.read().await.get(terminal_id).cloned() else {
+            return false;
+        };
+        let mut state = terminal.lock().await;
+        match &mut *state {
+            TerminalState::Pty { child, .. } => matches!(child.try_wait(), Ok(Some(_))),
+            _ => false,
+        }
+    }
+
+    /// Drop a supervised terminal the supervisor reported gone
+    pub async fn forget(&self, terminal_id: &str) {
+        self.terminals.write().await.remove(terminal_id);
+    }
+
     /// Close terminal
+    ///
+    /// Local shells are sent SIGHUP and killed if still running after
+    /// [`CLOSE_GRACE`]; this returns without waiting for them.
     pub async fn close(&self, terminal_id: &str) -> Result<(), TerminalError> {
-        let mut terminals = self.terminals.write().await;
+        let terminal = self
+            .terminals
+            .write()
+            .await
+            .remove(terminal_id)
+            .ok_or_else(|| TerminalError::NotFound(terminal_id.to_string()))?;
 
-        if let Some(terminal) = terminals.remove(terminal_id) {
-            let mut state = terminal.lock().await;
-            // Kill child process
-            if let Err(e) = state.child.kill() {
-                tracing::warn!("Error killing terminal process {}: {}", terminal_id, e);
+        let state = terminal.lock().await;
+        match &*state {
+            TerminalState::Pty { .. } => {
+                drop(state);
+                let mut closing = self.closing.lock().await;
+                // Forget shells that are already gone
+                while closing.try_join_next().is_some() {}
+                closing.spawn(hang_up(
+                    terminal_id.to_string(),
+                    terminal,
+                    self.cgroup_locks.clone(),
+                ));
             }
-            // Wait for process to exit
-            let _ = state.child.wait();
-            tracing::info!("Terminal {} closed", terminal_id);
-            Ok(())
-        } else {
-            Err(TerminalError::NotFound(terminal_id.to_string()))
+            TerminalState::Ssh(shell) => shell.close(),
+            TerminalState::Supervised { .. } => {
+                if let Some(supervisor) = &self.supervisor {
+                    supervisor.close(terminal_id)?;
+                }
+            }
+        }
+        tracing::info!("Terminal {} closed", terminal_id);
+        Ok(())
+    }
+
+    /// Wait until every closed shell has exited
+    pub async fn wait_closed(&self) {
+        let mut closing = self.closing.lock().await;
+        while closing.join_next().await.is_some() {}
+    }
+}
+
+/// Hang up a local shell, escalating to SIGKILL after [`CLOSE_GRACE`]
+async fn hang_up(
+    terminal_id: String,
+    terminal: Arc<Mutex<TerminalState>>,
+    cgroup_locks: CgroupLocks,
+) {
+    let mut state = terminal.lock().await;
+    let TerminalState::Pty { child, cgroup, .. } = &mut *state else {
+        return;
+    };
+
+    // An exited shell's pid may already belong to another process
+    let running = matches!(child.try_wait(), Ok(None));
+    if let Some(pid) = child
+        .process_id()
+        .filter(|_| running)
+        .map(|pid| Pid::from_raw(pid as i32))
+    {
+        let _ = kill(pid, NixSignal::SIGHUP);
+        let deadline = Instant::now() + CLOSE_GRACE;
+        let mut killed = false;
+        // Polled rather than waited on, so no runtime thread blocks
+        while let Ok(None) = child.try_wait() {
+            if !killed && Instant::now() >= deadline {
+                tracing::info!(
+                    "Terminal {} still running after SIGHUP, killing it",
+                    terminal_id
+                );
+                let _ = kill(pid, NixSignal::SIGKILL);
+                killed = true;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    if let Some(cgroup) = cgroup {
+        let _guard = cgroup_locks.lock(cgroup).await;
+        remove_cgroup(&terminal_id, cgroup).await;
+    }
+}
+
+/// Wait until a starting shell has joined its cgroup
+///
+/// The shell joins it itself before exec, and exits if it cannot.
+async fn wait_joined(
+    child: &mut (dyn Child + Send + Sync),
+    cgroup: &CgroupSpec,
+) -> Result<(), TerminalError> {
+    let error = |message: String| TerminalError::CgroupError(message);
+    let pid = child
+        .process_id()
+        .ok_or_else(|| error("No shell pid".to_string()))?;
+    let deadline = Instant::now() + JOIN_TIMEOUT;
+    loop {
+        if cgroup.contains(pid).map_err(|e| error(e.to_string()))? {
+            return Ok(());
+        }
+        if let Ok(Some(_)) = child.try_wait() {
+            return Err(error(format!(
+                "Shell exited before joining cgroup {}",
+                cgroup.path.display()
+            )));
         }
+        if Instant::now() >= deadline {
+            return Err(error(format!(
+                "Shell did not join cgroup {}",
+                cgroup.path.display()
+            )));
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Wait for a killed shell to exit, off the async runtime
+async fn reap(mut child: Box<dyn Child + Send + Sync>) {
+    let _ = tokio::task::spawn_blocking(move || child.wait()).await;
+}
+
+/// Remove a terminal's own cgroup after its shell failed to start
+fn discard_cgroup(cgroup: Option<&CgroupSpec>) {
+    if let Some(cgroup) = cgroup.filter(|c| !c.shared) {
+        let _ = cgroup.remove();
+    }
+}
+
+/// Remove a shell's cgroup once its processes are gone
+///
+/// A shared (per-user) cgroup still in use by another shell is left alone.
+async fn remove_cgroup(terminal_id: &str, cgroup: &CgroupSpec) {
+    let deadline = Instant::now() + CLOSE_GRACE;
+    loop {
+        match cgroup.remove() {
+            Ok(()) => return,
+            Err(e) if e.kind() == std::io::ErrorKind::ResourceBusy => {
+                if cgroup.shared {
+                    return;
+                }
+                if Instant::now() >= deadline {
+                    tracing::warn!(
+                        "Cgroup {} of terminal {} still busy, leaving it",
+                        cgroup.path.display(),
+                        terminal_id
+                    );
+                    return;
+                }
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to remove cgroup {} of terminal {}: {}",
+                    cgroup.path.display(),
+                    terminal_id,
+                    e
+                );
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+fn nix_signal(signal: Signal) -> NixSignal {
+    match signal {
+        Signal::Int => NixSignal::SIGINT,
+        Signal::Term => NixSignal::SIGTERM,
+        Signal::Hup => NixSignal::SIGHUP,
+        Signal::Kill => NixSignal::SIGKILL,
+        Signal::Tstp => NixSignal::SIGTSTP,
+        Signal::Cont => NixSignal::SIGCONT,
+    }
+}
+
+fn ssh_signal(signal: Signal) -> Sig {
+    match signal {
+        Signal::Int => Sig::INT,
+        Signal::Term => Sig::TERM,
+        Signal::Hup => Sig::HUP,
+        Signal::Kill => Sig::KILL,
+        Signal::Tstp => Sig::Custom("TSTP".to_string()),
+        Signal::Cont => Sig::Custom("CONT".to_string()),
     }
 }
 
@@ -204,3 +742,30 @@ fn get_default_shell() -> &'static str {
             .unwrap_or("/bin/bash")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cgroup_locks() {
+        let locks = CgroupLocks::default();
+        let mut cgroup = CgroupSpec {
+            path: PathBuf::from("/sys/fs/cgroup/webshell/user-alice"),
+            shared: true,
+            cpu: None,
+            memory_mb: None,
+            pids: None,
+        };
+
+        let guard = locks.lock(&cgroup).await;
+        assert!(guard.is_some());
+        let second = tokio::time::timeout(Duration::from_millis(50), locks.lock(&cgroup));
+        assert!(second.await.is_err());
+        drop(guard);
+        assert!(locks.lock(&cgroup).await.is_some());
+
+        cgroup.shared = false;
+        assert!(locks.lock(&cgroup).await.is_none());
+    }
+}
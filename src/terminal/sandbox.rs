@@ -0,0 +1,483 @@
+//! Namespace sandbox
+//!
+//! With `sandbox` set, local shells are started through `webshell sandbox`,
+//! which runs them in new mount, pid, network, uts and ipc namespaces:
+//!
+//! - the root filesystem is `sandbox_rootfs` (default: the host's `/`) under
+//!   a tmpfs overlay, so changes are thrown away with the terminal
+//! - `/proc`, `/dev` and `/tmp` are private, and `sandbox_home` is an empty
+//!   tmpfs if set
+//! - there is only a loopback interface, unless `sandbox_network` is set
+//! - the shell runs as `sandbox_uid`, without a way to regain privileges
+//! - the environment holds only `PATH`, `LANG`, `TERM`, `HOME`, `USER`,
+//!   `LOGNAME` and the profile's own variables, none of the server's
+//!
+//! Creating the namespaces needs root. Only the root filesystem itself is
+//! overlaid; filesystems mounted below it on the host are not visible.
+
+use portable_pty::CommandBuilder;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::cli::SandboxArgs;
+use crate::config::Config;
+
+/// Variables passed on from the server's environment
+const INHERITED_ENV: [&str; 2] = ["PATH", "LANG"];
+
+/// `PATH` if the server has none
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// How to sandbox a shell
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SandboxSpec {
+    /// Lower layer of the root filesystem (default: `/`)
+    pub rootfs: Option<PathBuf>,
+    /// Private tmpfs home directory
+    pub home: Option<PathBuf>,
+    /// Keep the host network
+    pub network: bool,
+    /// User and group id the shell runs as
+    pub uid: u32,
+}
+
+impl SandboxSpec {
+    /// How new local shells are sandboxed, if at all
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.sandbox.then(|| Self {
+            rootfs: config.sandbox_rootfs.as_ref().map(PathBuf::from),
+            home: config.sandbox_home.as_ref().map(PathBuf::from),
+            network: config.sandbox_network,
+            uid: config.sandbox_uid,
+        })
+    }
+
+    /// Arguments to `webshell` that run `program` in this sandbox
+    ///
+    /// The sandbox joins `cgroup` before starting anything.
+    pub fn args(&self, cgroup: Option<&Path>, program: &str, args: &[String]) -> Vec<OsString> {
+        let mut argv: Vec<OsString> = vec!["sandbox".into()];
+        if let Some(rootfs) = &self.rootfs {
+            argv.extend(["--rootfs".into(), rootfs.into()]);
+        }
+        if let Some(home) = &self.home {
+            argv.extend(["--home".into(), home.into()]);
+        }
+        if self.network {
+            argv.push("--network".into());
+        }
+        argv.extend(["--uid".into(), self.uid.to_string().into()]);
+        if let Some(cgroup) = cgroup {
+            argv.extend(["--cgroup".into(), cgroup.into()]);
+        }
+        argv.push("--".into());
+        argv.push(program.into());
+        argv.extend(args.iter().map(OsString::from));
+        argv
+    }
+
+    /// Command that runs `program` in this sandbox through `exe`
+    ///
+    /// Starts from an empty environment, so the server's secrets stay out;
+    /// the sandbox sets `HOME`, `USER` and `LOGNAME` itself.
+    pub fn command(
+        &self,
+        exe: impl AsRef<Path>,
+        cgroup: Option<&Path>,
+        program: &str,
+        args: &[String],
+    ) -> CommandBuilder {
+        let mut cmd = CommandBuilder::new(exe.as_ref());
+        cmd.args(self.args(cgroup, program, args));
+        cmd.cwd("/");
+        cmd.env_clear();
+        for key in INHERITED_ENV {
+            if let Some(value) = std::env::var_os(key) {
+                cmd.env(key, value);
+            }
+        }
+        if cmd.get_env("PATH").is_none() {
+            cmd.env("PATH", DEFAULT_PATH);
+        }
+        cmd
+    }
+}
+
+/// Run `webshell sandbox`, exiting with the command's exit code
+///
+/// Must run before any other thread is started, since a multi-threaded
+/// process cannot enter new namespaces.
+pub fn run(args: &SandboxArgs) -> ! {
+    exit_with(enter(args))
+}
+
+fn exit_with(result: anyhow::Result<i32>) -> ! {
+    match result {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("webshell sandbox: {:#}", e);
+            std::process::exit(126)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+use linux::enter;
+
+#[cfg(not(target_os = "linux"))]
+fn enter(_args: &SandboxArgs) -> anyhow::Result<i32> {
+    anyhow::bail!("Sandboxed terminals need Linux")
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use anyhow::Context;
+    use nix::mount::{mount, umount2, MntFlags, MsFlags};
+    use nix::sched::{unshare, CloneFlags};
+    use nix::sys::prctl;
+    use nix::sys::signal::{kill, SigSet, Signal};
+    use nix::sys::signalfd::SignalFd;
+    use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+    use nix::unistd::{
+        chdir, execvp, fork, pivot_root, setgid, setgroups, sethostname, setuid, ForkResult, Gid,
+        Pid, Uid, User,
+    };
+    use std::convert::Infallible;
+    use std::ffi::CString;
+    use std::fs;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+
+    use super::exit_with;
+    use crate::cli::SandboxArgs;
+
+    /// Hostname inside the sandbox
+    const HOSTNAME: &str = "sandbox";
+
+    /// Devices bind-mounted from the host into the sandbox's `/dev`
+    const DEVICES: [&str; 6] = ["null", "zero", "full", "random", "urandom", "tty"];
+
+    /// Signals passed on to the sandboxed shell
+    const FORWARDED: [Signal; 8] = [
+        Signal::SIGHUP,
+        Signal::SIGINT,
+        Signal::SIGQUIT,
+        Signal::SIGTERM,
+        Signal::SIGUSR1,
+        Signal::SIGUSR2,
+        Signal::SIGTSTP,
+        Signal::SIGCONT,
+    ];
+
+    /// Create the namespaces and wait for the sandbox's init
+    pub fn enter(args: &SandboxArgs) -> anyhow::Result<i32> {
+        if let Some(cgroup) = &args.cgroup {
+            crate::terminal::cgroup::join(cgroup)
+                .with_context(|| format!("Failed to join cgroup {}", cgroup.display()))?;
+        }
+
+        // Forwarded signals and SIGCHLD are read from a signalfd; blocking them
+        // before forking means none are missed
+        let mut signals: SigSet = FORWARDED.into_iter().collect();
+        signals.add(Signal::SIGCHLD);
+        signals.thread_block()?;
+
+        let mut namespaces = CloneFlags::CLONE_NEWNS
+            | CloneFlags::CLONE_NEWPID
+            | CloneFlags::CLONE_NEWUTS
+            | CloneFlags::CLONE_NEWIPC;
+        if !args.network {
+            namespaces |= CloneFlags::CLONE_NEWNET;
+        }
+        unshare(namespaces).context("Failed to create namespaces (needs root)")?;
+
+        // SAFETY: the process is single-threaded (see `run`)
+        match unsafe { fork() }.context("Failed to fork")? {
+            ForkResult::Child => exit_with(init(args, &signals)),
+            ForkResult::Parent { child } => supervise(child, false, &signals),
+        }
+    }
+
+    /// Pid 1 of the sandbox: build the root filesystem, then run the command
+    fn init(args: &SandboxArgs, signals: &SigSet) -> anyhow::Result<i32> {
+        // Everything in the namespace dies with the outer process
+        prctl::set_pdeathsig(Signal::SIGKILL)?;
+
+        setup_root(args)?;
+        sethostname(HOSTNAME).context("Failed to set hostname")?;
+        if !args.network {
+            loopback_up().context("Failed to bring up loopback")?;
+        }
+
+        // SAFETY: the process is single-threaded
+        match unsafe { fork() }.context("Failed to fork")? {
+            ForkResult::Child => exit_with(exec(args, signals).map(|never| match never {})),
+            ForkResult::Parent { child } => supervise(child, true, signals),
+        }
+    }
+
+    /// Assemble the sandbox's root filesystem and switch to it
+    fn setup_root(args: &SandboxArgs) -> anyhow::Result<()> {
+        // Keep these mounts from propagating back to the host
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+            None::<&str>,
+        )
+        .context("Failed to make mounts private")?;
+
+        // Held open since the staging tmpfs may hide it
+        let lower_path = args.rootfs.as_deref().unwrap_or(Path::new("/"));
+        let lower = fs::File::open(lower_path)
+            .with_context(|| format!("Failed to open {}", lower_path.display()))?;
+
+        // The overlay's upper layer lives on a tmpfs gone with the namespace
+        let stage = Path::new("/tmp");
+        mount_fs("tmpfs", stage, MsFlags::MS_NOSUID, "mode=0700")?;
+        let (upper, work, root) = (stage.join("upper"), stage.join("work"), stage.join("root"));
+        for dir in [&upper, &work, &root] {
+            fs::create_dir(dir)?;
+        }
+        mount_fs(
+            "overlay",
+            &root,
+            MsFlags::empty(),
+            &format!(
+                "lowerdir=/proc/self/fd/{},upperdir={},workdir={}",
+                lower.as_raw_fd(),
+                upper.display(),
+                work.display()
+            ),
+        )?;
+        drop(lower);
+
+        mount_fs(
+            "proc",
+            &mount_point(&root, "proc")?,
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            "",
+        )?;
+        setup_dev(&mount_point(&root, "dev")?)?;
+        mount_fs(
+            "tmpfs",
+            &mount_point(&root, "tmp")?,
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            "mode=1777",
+        )?;
+        if let Some(home) = &args.home {
+            mount_fs(
+                "tmpfs",
+                &mount_point(&root, home)?,
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                &format!("mode=0700,uid={0},gid={0}", args.uid),
+            )?;
+        }
+
+        // Swap in the new root and detach the host's
+        chdir(&root)?;
+        pivot_root(".", ".").context("Failed to switch root")?;
+        umount2(".", MntFlags::MNT_DETACH).context("Failed to detach the host root")?;
+        chdir("/")?;
+        Ok(())
+    }
+
+    /// A minimal `/dev` with a private pty namespace
+    fn setup_dev(dev: &Path) -> anyhow::Result<()> {
+        mount_fs("tmpfs", dev, MsFlags::MS_NOSUID, "mode=0755")?;
+        for name in DEVICES {
+            bind(&Path::new("/dev").join(name), &dev.join(name))?;
+        }
+        // The terminal itself, so ttyname(3) finds it outside the private /dev/pts
+        if let Ok(tty) = fs::read_link("/proc/self/fd/0") {
+            if tty.starts_with("/dev/pts") {
+                bind(&tty, &dev.join("console"))?;
+            }
+        }
+        mount_fs(
+            "devpts",
+            &mount_point(dev, "pts")?,
+            MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+            "newinstance,ptmxmode=0666,mode=0620",
+        )?;
+        mount_fs(
+            "tmpfs",
+            &mount_point(dev, "shm")?,
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            "mode=1777",
+        )?;
+        for (link, target) in [
+            ("ptmx", "pts/ptmx"),
+            ("fd", "/proc/self/fd"),
+            ("stdin", "/proc/self/fd/0"),
+            ("stdout", "/proc/self/fd/1"),
+            ("stderr", "/proc/self/fd/2"),
+        ] {
+            symlink(target, dev.join(link))?;
+        }
+        Ok(())
+    }
+
+    /// Create `path` (absolute or relative) under `root` to mount on
+    fn mount_point(root: &Path, path: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
+        let path = path.as_ref();
+        let dir = root.join(path.strip_prefix("/").unwrap_or(path));
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(dir)
+    }
+
+    /// Bind-mount a device node onto a new file
+    fn bind(source: &Path, target: &Path) -> anyhow::Result<()> {
+        fs::File::create(target)?;
+        mount(
+            Some(source),
+            target,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .with_context(|| format!("Failed to bind {}", source.display()))
+    }
+
+    fn mount_fs(fstype: &str, target: &Path, flags: MsFlags, data: &str) -> anyhow::Result<()> {
+        mount(Some(fstype), target, Some(fstype), flags, Some(data))
+            .with_context(|| format!("Failed to mount {} on {}", fstype, target.display()))
+    }
+
+    /// Bring up `lo`, the only interface of a new network namespace
+    fn loopback_up() -> anyhow::Result<()> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        // SAFETY: ifreq is plain data, for which all zeroes is valid
+        let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+        for (dst, src) in request.ifr_name.iter_mut().zip(b"lo\0") {
+            *dst = *src as libc::c_char;
+        }
+        // SAFETY: the request names an interface and has room for its flags
+        unsafe {
+            if libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFFLAGS, &mut request) < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            if libc::ioctl(socket.as_raw_fd(), libc::SIOCSIFFLAGS, &request) < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop privileges and replace this process with the command
+    fn exec(args: &SandboxArgs, signals: &SigSet) -> anyhow::Result<Infallible> {
+        let (uid, gid) = (Uid::from_raw(args.uid), Gid::from_raw(args.uid));
+        setgroups(&[gid]).context("Failed to set groups")?;
+        setgid(gid).context("Failed to set group id")?;
+        setuid(uid).context("Failed to set user id")?;
+        prctl::set_no_new_privs()?;
+
+        let home = args.home.as_deref().unwrap_or(Path::new("/"));
+        chdir(home)?;
+        std::env::set_var("HOME", home);
+        // Looked up in the sandbox's own /etc/passwd
+        let user = User::from_uid(uid)
+            .ok()
+            .flatten()
+            .map_or_else(|| args.uid.to_string(), |user| user.name);
+        std::env::set_var("USER", &user);
+        std::env::set_var("LOGNAME", &user);
+        signals.thread_unblock()?;
+
+        let argv = args
+            .command
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        execvp(&argv[0], &argv).with_context(|| format!("Failed to run {}", args.command[0]))
+    }
+
+    /// Forward signals to `child` until it exits, then return its exit code
+    ///
+    /// As pid 1 of the namespace (`reap_all`) this also reaps orphans.
+    fn supervise(child: Pid, reap_all: bool, signals: &SigSet) -> anyhow::Result<i32> {
+        let signalfd = SignalFd::new(signals)?;
+        let target = (!reap_all).then_some(child);
+        loop {
+            loop {
+                match waitpid(target, Some(WaitPidFlag::WNOHANG)).context("Failed to wait")? {
+                    WaitStatus::Exited(pid, code) if pid == child => return Ok(code),
+                    WaitStatus::Signaled(pid, signal, _) if pid == child => {
+                        return Ok(128 + signal as i32)
+                    }
+                    WaitStatus::StillAlive => break,
+                    _ => {}
+                }
+            }
+
+            if let Some(info) = signalfd.read_signal()? {
+                match Signal::try_from(info.ssi_signo as i32) {
+                    Ok(Signal::SIGCHLD) | Err(_) => {}
+                    Ok(signal) => {
+                        let _ = kill(child, signal);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, Command};
+    use clap::Parser;
+
+    #[test]
+    fn test_args() {
+        let spec = SandboxSpec {
+            rootfs: Some(PathBuf::from("/srv/guest")),
+            home: Some(PathBuf::from("/home/guest")),
+            network: false,
+            uid: 1000,
+        };
+        let argv = spec.args(
+            Some(Path::new("/sys/fs/cgroup/webshell/terminal-t1")),
+            "/bin/bash",
+            &["--login".to_string()],
+        );
+
+        let cli = Cli::parse_from(std::iter::once(OsString::from("webshell")).chain(argv));
+        let Some(Command::Sandbox(args)) = cli.command else {
+            panic!("not a sandbox command");
+        };
+        assert_eq!(args.rootfs, spec.rootfs);
+        assert_eq!(args.home, spec.home);
+        assert!(!args.network);
+        assert_eq!(args.uid, 1000);
+        assert_eq!(
+            args.cgroup,
+            Some(PathBuf::from("/sys/fs/cgroup/webshell/terminal-t1"))
+        );
+        assert_eq!(args.command, ["/bin/bash", "--login"]);
+    }
+
+    #[test]
+    fn test_command_env() {
+        std::env::set_var("WEBSHELL_SANDBOX_TEST_SECRET", "hunter2");
+        let spec = SandboxSpec {
+            rootfs: None,
+            home: None,
+            network: false,
+            uid: 1000,
+        };
+        let cmd = spec.command("/usr/bin/webshell", None, "/bin/sh", &[]);
+
+        assert_eq!(cmd.get_env("WEBSHELL_SANDBOX_TEST_SECRET"), None);
+        assert!(cmd.get_env("PATH").is_some());
+        assert!(cmd
+            .iter_full_env_as_str()
+            .all(|(key, _)| INHERITED_ENV.contains(&key)));
+        assert_eq!(cmd.get_argv()[0], "/usr/bin/webshell");
+        assert_eq!(cmd.get_argv()[1], "sandbox");
+    }
+}
@@ -0,0 +1,70 @@
+//! Scrollback buffer
+//!
+//! Keeps the most recent terminal output so late joiners can catch up.
+
+use std::collections::VecDeque;
+
+/// Default scrollback capacity (bytes)
+pub const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+/// Bounded buffer of recent output
+#[derive(Debug)]
+pub struct Scrollback {
+    data: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Append output, dropping the oldest bytes beyond capacity
+    pub fn push(&mut self, output: impl AsRef<[u8]>) {
+        self.data.extend(output.as_ref());
+        let excess = self.data.len().saturating_sub(self.capacity);
+        self.data.drain(..excess);
+    }
+
+    /// Buffered output, starting at the first complete character
+    pub fn contents(&self) -> String {
+        let (front, back) = self.data.as_slices();
+        let bytes = [front, back].concat();
+        // Skip continuation bytes left over from a truncated character
+        let start = bytes
+            .iter()
+            .position(|b| (*b as i8) >= -0x40)
+            .unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[start..]).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrollback_truncation() {
+        let mut scrollback = Scrollback::new(8);
+        scrollback.push("hello ");
+        scrollback.push("world");
+        assert_eq!(scrollback.contents(), "lo world");
+
+        // A multi-byte character cut in half is dropped
+        let mut scrollback = Scrollback::new(4);
+        scrollback.push("é");
+        scrollback.push("abc");
+        assert_eq!(scrollback.contents(), "abc");
+        scrollback.push("é");
+        assert_eq!(scrollback.contents(), "bcé");
+    }
+}
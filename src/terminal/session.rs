@@ -3,52 +3,499 @@
 //! Manages terminal sessions with lifecycle handling and timeout cleanup.
 
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+use tokio::sync::{mpsc, RwLock};
 use tokio::time::{interval, Duration};
 
+use super::cgroup::{self, CgroupSpec};
 use super::error::TerminalError;
+use super::process;
 use super::pty::{PtyManager, TerminalHandle};
-use crate::config::Config;
+use super::sandbox::SandboxSpec;
+use super::scrollback::Scrollback;
+use super::tmux;
+use super::usage::{Limit, ProcessTable, ResourceUsage};
+use crate::audit::AuditLog;
+use crate::config::{Profile, ProfileBackend, SharedConfig};
+use crate::ssh::{ShellOptions, SshAuth, SshConfig};
+use crate::supervisor::SupervisorClient;
+use crate::types::{
+    Envelope, Participant, ParticipantRole, ProcessStatus, ShellExit, ShellOutput, Signal,
+    SignalTarget, TerminalNotice, TerminalParticipants, TerminalResize, TerminalRole,
+    TerminalStatus, TerminalUpdated, WsMessage,
+};
+
+/// A WebSocket connection that can attach to terminals
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub connection_id: String,
+    pub username: String,
+    pub tx: mpsc::UnboundedSender<Envelope>,
+    /// Never given input control (e.g. read-only share link viewers)
+    pub read_only: bool,
+    /// Left out of participant lists (silent admin shadowing)
+    pub hidden: bool,
+    /// Share link a guest connection was opened through
+    pub share_id: Option<String>,
+}
+
+/// Clients attached to a terminal, shared with the PTY reader thread
+type Clients = Arc<Mutex<Vec<Client>>>;
+
+/// Terminal backend type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Local PTY
+    Local,
+    /// Remote shell over SSH
+    Ssh,
+}
+
+/// How often foreground processes are checked
+const STATUS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often resource usage is sampled
+const USAGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Longest terminal name
+const MAX_NAME_LEN: usize = 100;
+/// Most tags on a terminal
+const MAX_TAGS: usize = 16;
+/// Longest tag
+const MAX_TAG_LEN: usize = 32;
+
+/// Terminal summary for the admin API and `term.list`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalInfo {
+    pub id: String,
+    /// Name set by the owner
+    pub name: Option<String>,
+    /// Display title: the name, else the tmux session or profile
+    pub title: String,
+    pub tags: Vec<String>,
+    pub owner: String,
+    pub pid: Option<u32>,
+    pub cols: u16,
+    pub rows: u16,
+    pub backend: Backend,
+    /// Host profile the terminal was opened with
+    pub profile: Option<String>,
+    /// tmux session the terminal is attached to
+    pub tmux_session: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+    pub idle_secs: u64,
+    /// Visible clients attached right now
+    pub attached: usize,
+    /// Foreground process and directory, as of the last check
+    pub status: ProcessStatus,
+    /// Resource usage of the shell's process tree, as of the last sample
+    pub usage: Option<ResourceUsage>,
+}
+
+/// Session details kept with a supervised terminal, to rebuild it after a restart
+#[derive(Debug, Serialize, Deserialize)]
+struct TerminalMeta {
+    owner: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    profile: Option<String>,
+    #[serde(default)]
+    tmux_session: Option<String>,
+    created_at: DateTime<Utc>,
+    #[serde(default)]
+    cgroup: Option<PathBuf>,
+    #[serde(default)]
+    sandboxed: bool,
+}
 
 /// Internal session state
 struct SessionState {
     handle: TerminalHandle,
+    owner: String,
+    /// Name and tags set by the owner
+    name: Option<String>,
+    tags: Vec<String>,
+    backend: Backend,
+    profile: Option<String>,
+    tmux_session: Option<String>,
+    cols: u16,
+    rows: u16,
+    created_at: DateTime<Utc>,
     last_activity: DateTime<Utc>,
-    connected: bool,
+    /// Users the owner has granted access to
+    shared_with: HashSet<String>,
+    /// Share links whose guests may attach, with their viewer limits
+    shared_links: HashMap<String, Option<usize>>,
+    /// Attached clients receiving output
+    clients: Clients,
+    /// Recent output replayed to newly attached clients
+    scrollback: Arc<Mutex<Scrollback>>,
+    /// Connection allowed to write input and set the size
+    controller: Option<String>,
+    /// Last reported foreground process and directory
+    status: ProcessStatus,
+    /// Last resource usage sample and when it was taken
+    usage: Option<(ResourceUsage, Instant)>,
+    /// Usage limits the terminal was over at the last sample
+    over_limits: Vec<Limit>,
+    /// cgroup the shell runs in, if any
+    cgroup: Option<PathBuf>,
+    /// OOM kills in the cgroup already reported
+    oom_kills: u64,
+    /// The shell runs in a namespace sandbox
+    sandboxed: bool,
+}
+
+impl SessionState {
+    /// Check if a user may attach to this terminal
+    fn can_attach(&self, username: &str) -> bool {
+        self.owner == username || self.shared_with.contains(username)
+    }
+
+    /// Check if a client may attach, by share link for guests
+    fn admits(&self, session_id: &str, client: &Client) -> Result<(), TerminalError> {
+        let denied = || TerminalError::AccessDenied(session_id.to_string());
+        let Some(share_id) = &client.share_id else {
+            return self
+                .can_attach(&client.username)
+                .then_some(())
+                .ok_or_else(denied);
+        };
+        let max_viewers = self.shared_links.get(share_id).ok_or_else(denied)?;
+        let viewers = lock(&self.clients)
+            .iter()
+            .filter(|c| c.share_id == client.share_id && c.connection_id != client.connection_id)
+            .count();
+        match max_viewers {
+            Some(max_viewers) if viewers >= *max_viewers => {
+                Err(TerminalError::ViewerLimit(session_id.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Details a supervisor keeps to rebuild this terminal
+    fn meta(&self) -> TerminalMeta {
+        TerminalMeta {
+            owner: self.owner.clone(),
+            name: self.name.clone(),
+            tags: self.tags.clone(),
+            profile: self.profile.clone(),
+            tmux_session: self.tmux_session.clone(),
+            created_at: self.created_at,
+            cgroup: self.cgroup.clone(),
+            sandboxed: self.sandboxed,
+        }
+    }
+
+    /// Summary of this terminal
+    fn info(&self, session_id: &str, now: DateTime<Utc>) -> TerminalInfo {
+        TerminalInfo {
+            id: session_id.to_string(),
+            name: self.name.clone(),
+            title: self
+                .name
+                .clone()
+                .or_else(|| self.tmux_session.clone())
+                .or_else(|| self.profile.clone())
+                .unwrap_or_else(|| "shell".to_string()),
+            tags: self.tags.clone(),
+            owner: self.owner.clone(),
+            pid: self.handle.pid,
+            cols: self.cols,
+            rows: self.rows,
+            backend: self.backend,
+            profile: self.profile.clone(),
+            tmux_session: self.tmux_session.clone(),
+            created_at: self.created_at,
+            last_activity: self.last_activity,
+            idle_secs: now
+                .signed_duration_since(self.last_activity)
+                .to_std()
+                .unwrap_or(Duration::ZERO)
+                .as_secs(),
+            attached: lock(&self.clients).iter().filter(|c| !c.hidden).count(),
+            status: self.status.clone(),
+            usage: self.usage.as_ref().map(|(usage, _)| usage.clone()),
+        }
+    }
+
+    /// Check if a connection is attached to this terminal
+    fn is_attached(&self, connection_id: &str) -> bool {
+        lock(&self.clients)
+            .iter()
+            .any(|c| c.connection_id == connection_id)
+    }
+
+    /// Attach a client, replaying the current size and scrollback
+    fn attach(&mut self, session_id: &str, client: &Client) {
+        let _ = client.tx.send(
+            WsMessage::TerminalResized(TerminalResize {
+                id: session_id.to_string(),
+                cols: self.cols,
+                rows: self.rows,
+            })
+            .into(),
+        );
+
+        {
+            // Hold the client lock so no output is lost or repeated in between
+            let mut clients = lock(&self.clients);
+            if !clients
+                .iter()
+                .any(|c| c.connection_id == client.connection_id)
+            {
+                let history = lock(&self.scrollback).contents();
+                if !history.is_empty() {
+                    let _ = client.tx.send(
+                        WsMessage::ShellOutput(ShellOutput {
+                            id: session_id.to_string(),
+                            output: history,
+                        })
+                        .into(),
+                    );
+                }
+                clients.push(client.clone());
+            }
+        }
+        if self.status != ProcessStatus::default() {
+            let _ = client.tx.send(self.status_message(session_id).into());
+        }
+
+        // The owner regains control of an uncontrolled terminal
+        if self.controller.is_none() && client.username == self.owner && !client.read_only {
+            self.set_controller(session_id, Some(client.connection_id.clone()));
+        } else {
+            self.send_role(session_id, &client.connection_id);
+        }
+        if !client.hidden {
+            self.broadcast_participants(session_id);
+        }
+    }
+
+    /// Check if a connection holds input control
+    fn is_controller(&self, connection_id: &str) -> bool {
+        self.controller.as_deref() == Some(connection_id)
+    }
+
+    /// Role of an attached connection
+    fn role_of(&self, connection_id: &str) -> ParticipantRole {
+        if self.is_controller(connection_id) {
+            ParticipantRole::Controller
+        } else {
+            ParticipantRole::Spectator
+        }
+    }
+
+    /// Tell a connection its current role
+    fn send_role(&self, session_id: &str, connection_id: &str) {
+        let role = self.role_of(connection_id);
+        for client in lock(&self.clients)
+            .iter()
+            .filter(|c| c.connection_id == connection_id)
+        {
+            let _ = client.tx.send(
+                WsMessage::TerminalRole(TerminalRole {
+                    id: session_id.to_string(),
+                    role,
+                })
+                .into(),
+            );
+        }
+    }
+
+    /// Move input control to another connection (or nobody)
+    fn set_controller(&mut self, session_id: &str, controller: Option<String>) {
+        let previous = std::mem::replace(&mut self.controller, controller);
+        if let Some(previous) = previous {
+            self.send_role(session_id, &previous);
+        }
+        if let Some(current) = self.controller.clone() {
+            self.send_role(session_id, &current);
+        }
+    }
+
+    /// Detach matching clients, returning control to the owner if needed
+    fn remove_clients<F>(&mut self, session_id: &str, matches: F)
+    where
+        F: Fn(&Client) -> bool,
+    {
+        lock(&self.clients).retain(|c| !matches(c));
+
+        let controller_gone = self
+            .controller
+            .as_deref()
+            .is_some_and(|id| !self.is_attached(id));
+        if controller_gone {
+            // Hand control back to one of the owner's connections, if any
+            let owner_connection = lock(&self.clients)
+                .iter()
+                .find(|c| c.username == self.owner)
+                .map(|c| c.connection_id.clone());
+            self.controller = None;
+            self.set_controller(session_id, owner_connection);
+        }
+        self.broadcast_participants(session_id);
+    }
+
+    /// Current participant list message
+    fn participants_message(&self, session_id: &str) -> WsMessage {
+        let participants = lock(&self.clients)
+            .iter()
+            .filter(|c| !c.hidden)
+            .map(|c| Participant {
+                connection_id: c.connection_id.clone(),
+                username: c.username.clone(),
+                role: self.role_of(&c.connection_id),
+            })
+            .collect();
+        WsMessage::TerminalParticipants(TerminalParticipants {
+            id: session_id.to_string(),
+            owner: self.owner.clone(),
+            participants,
+        })
+    }
+
+    /// Current foreground process message
+    fn status_message(&self, session_id: &str) -> WsMessage {
+        WsMessage::TerminalStatus(TerminalStatus {
+            id: session_id.to_string(),
+            status: self.status.clone(),
+        })
+    }
+
+    /// Send the participant list to every attached client
+    fn broadcast_participants(&self, session_id: &str) {
+        broadcast(&self.clients, self.participants_message(session_id));
+    }
+}
+
+/// Lock a mutex, ignoring poisoning
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Send a message to every attached client, dropping disconnected ones
+fn broadcast(clients: &Clients, msg: WsMessage) {
+    lock(clients).retain(|c| c.tx.send(msg.clone().into()).is_ok());
+}
+
+/// Trim, check and de-duplicate terminal tags, keeping their order
+fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, TerminalError> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_string();
+        if tag.is_empty() || normalized.contains(&tag) {
+            continue;
+        }
+        if tag.chars().count() > MAX_TAG_LEN {
+            return Err(TerminalError::InvalidRequest(format!(
+                "Tag is longer than {} characters",
+                MAX_TAG_LEN
+            )));
+        }
+        normalized.push(tag);
+    }
+    if normalized.len() > MAX_TAGS {
+        return Err(TerminalError::InvalidRequest(format!(
+            "More than {} tags",
+            MAX_TAGS
+        )));
+    }
+    Ok(normalized)
+}
+
+/// Output callback fanning out to every attached client, keeping scrollback
+fn fan_out(
+    session_id: &str,
+    clients: &Clients,
+    scrollback: &Arc<Mutex<Scrollback>>,
+) -> impl Fn(Vec<u8>) + Send + 'static {
+    let output_clients = clients.clone();
+    let output_scrollback = scrollback.clone();
+    let output_id = session_id.to_string();
+    move |data: Vec<u8>| {
+        if let Ok(output) = String::from_utf8(data) {
+            let mut clients = lock(&output_clients);
+            lock(&output_scrollback).push(&output);
+            let msg = WsMessage::ShellOutput(ShellOutput {
+                id: output_id.clone(),
+                output,
+            });
+            clients.retain(|c| c.tx.send(msg.clone().into()).is_ok());
+        }
+    }
 }
 
 /// Manages terminal sessions with lifecycle handling
 pub struct SessionManager {
     sessions: Arc<RwLock<HashMap<String, SessionState>>>,
     pty_manager: Arc<PtyManager>,
-    max_terminals: usize,
-    idle_timeout: u64,
-    app_config: Arc<Config>,
+    app_config: SharedConfig,
+    audit: AuditLog,
 }
 
 impl SessionManager {
-    pub fn new(app_config: Arc<Config>) -> Self {
+    pub fn new(
+        app_config: SharedConfig,
+        audit: AuditLog,
+        supervisor: Option<SupervisorClient>,
+    ) -> Self {
+        let exits = supervisor.as_ref().map(SupervisorClient::exits);
+        let pty_manager = match supervisor {
+            Some(supervisor) => PtyManager::with_supervisor(supervisor),
+            None => PtyManager::new(),
+        };
         let manager = Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
-            pty_manager: Arc::new(PtyManager::new()),
-            max_terminals: app_config.max_terminals,
-            idle_timeout: app_config.idle_timeout,
+            pty_manager: Arc::new(pty_manager),
             app_config,
+            audit,
         };
 
         // Start cleanup task
         manager.start_cleanup_task();
+        manager.start_status_task();
+        manager.start_usage_task();
+        if let Some(exits) = exits {
+            manager.start_exit_task(exits);
+        }
 
         manager
     }
 
+    /// Start background task removing supervised terminals whose shell exited
+    fn start_exit_task(&self, mut exits: mpsc::UnboundedReceiver<String>) {
+        let sessions = self.sessions.clone();
+        let pty_manager = self.pty_manager.clone();
+
+        tokio::spawn(async move {
+            while let Some(id) = exits.recv().await {
+                pty_manager.forget(&id).await;
+                if let Some(session) = sessions.write().await.remove(&id) {
+                    tracing::info!("Terminal {} exited", id);
+                    broadcast(
+                        &session.clients,
+                        WsMessage::ShellExit(ShellExit { id, code: None }),
+                    );
+                }
+            }
+        });
+    }
+
     /// Start background task to cleanup idle sessions
     fn start_cleanup_task(&self) {
         let sessions = self.sessions.clone();
         let pty_manager = self.pty_manager.clone();
-        let timeout = Duration::from_secs(self.idle_timeout);
+        let app_config = self.app_config.clone();
 
         tokio::spawn(async move {
             let mut interval = interval(Duration::from_secs(60));
@@ -56,6 +503,8 @@ impl SessionManager {
             loop {
                 interval.tick().await;
 
+                // Read every round so a reloaded timeout applies
+                let timeout = Duration::from_secs(app_config.get().idle_timeout);
                 let now = Utc::now();
                 let mut to_remove = vec![];
 
@@ -63,7 +512,7 @@ impl SessionManager {
                 {
                     let sessions = sessions.read().await;
                     for (id, session) in sessions.iter() {
-                        if !session.connected {
+                        if lock(&session.clients).is_empty() {
                             let idle_duration = now
                                 .signed_duration_since(session.last_activity)
                                 .to_std()
@@ -90,56 +539,299 @@ impl SessionManager {
         });
     }
 
+    /// Start background task reporting foreground processes
+    ///
+    /// Attached clients get `term.status` whenever a local terminal's
+    /// foreground process or directory changes, and a `term.notice` when a
+    /// process in its cgroup is killed for running out of memory.
+    fn start_status_task(&self) {
+        let sessions = self.sessions.clone();
+        let pty_manager = self.pty_manager.clone();
+
+        tokio::spawn(async move {
+            let mut interval = interval(STATUS_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let shells: Vec<(String, u32)> = sessions
+                    .read()
+                    .await
+                    .iter()
+                    .filter(|(_, session)| session.backend == Backend::Local)
+                    .filter_map(|(id, session)| {
+                        let pid = session.handle.pid?;
+                        let shell = if session.sandboxed {
+                            process::sandboxed_shell(pid)?
+                        } else {
+                            pid
+                        };
+                        Some((id.clone(), shell))
+                    })
+                    .collect();
+
+                let mut statuses = Vec::with_capacity(shells.len());
+                for (id, pid) in shells {
+                    let foreground = pty_manager.foreground(&id).await;
+                    statuses.push((id, process::status(pid, foreground)));
+                }
+
+                let mut sessions = sessions.write().await;
+                for (id, status) in statuses {
+                    let Some(session) = sessions.get_mut(&id) else {
+                        continue;
+                    };
+                    if session.status != status {
+                        session.status = status;
+                        broadcast(&session.clients, session.status_message(&id));
+                    }
+
+                    let oom_kills = session.cgroup.as_deref().and_then(cgroup::oom_kills);
+                    if let Some(oom_kills) = oom_kills.filter(|&n| n > session.oom_kills) {
+                        let killed = oom_kills - session.oom_kills;
+                        session.oom_kills = oom_kills;
+                        tracing::warn!(
+                            "Terminal {} of {}: {} process(es) killed, out of memory",
+                            id,
+                            session.owner,
+                            killed
+                        );
+                        broadcast(
+                            &session.clients,
+                            WsMessage::TerminalNotice(TerminalNotice {
+                                id: id.clone(),
+                                message: format!(
+                                    "Out of memory: {} process(es) killed by the memory limit",
+                                    killed
+                                ),
+                            }),
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    /// Start background task sampling resource usage
+    ///
+    /// Going over a configured `usage_*` limit is logged and audited once,
+    /// until usage drops back under it.
+    fn start_usage_task(&self) {
+        let sessions = self.sessions.clone();
+        let app_config = self.app_config.clone();
+        let audit = self.audit.clone();
+
+        tokio::spawn(async move {
+            let mut interval = interval(USAGE_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let shells: Vec<(String, u32)> = sessions
+                    .read()
+                    .await
+                    .iter()
+                    .filter(|(_, session)| session.backend == Backend::Local)
+                    .filter_map(|(id, session)| Some((id.clone(), session.handle.pid?)))
+                    .collect();
+                let sampled = tokio::task::spawn_blocking(move || {
+                    let table = ProcessTable::read();
+                    shells
+                        .into_iter()
+                        .filter_map(|(id, pid)| Some((id, table.usage(pid)?)))
+                        .collect::<Vec<_>>()
+                })
+                .await;
+                let Ok(sampled) = sampled else {
+                    continue;
+                };
+
+                let config = app_config.get();
+                let now = Instant::now();
+                let mut sessions = sessions.write().await;
+                for (id, mut usage) in sampled {
+                    let Some(session) = sessions.get_mut(&id) else {
+                        continue;
+                    };
+                    if let Some((previous, at)) = &session.usage {
+                        let elapsed = now.duration_since(*at).as_secs_f64();
+                        if elapsed > 0.0 {
+                            usage.cpu_percent =
+                                (usage.cpu_secs - previous.cpu_secs).max(0.0) / elapsed * 100.0;
+                        }
+                    }
+
+                    let over = usage.exceeded(&config);
+                    for limit in over.iter().filter(|l| !session.over_limits.contains(l)) {
+                        tracing::warn!(
+                            "Terminal {} of {} is over its {} limit",
+                            id,
+                            session.owner,
+                            limit.name()
+                        );
+                        audit.record(
+                            "system",
+                            "terminal.usage_warning",
+                            &id,
+                            serde_json::json!({
+                                "owner": session.owner,
+                                "limit": limit.name(),
+                                "usage": usage,
+                            }),
+                        );
+                    }
+                    session.over_limits = over;
+                    session.usage = Some((usage, now));
+                }
+            }
+        });
+    }
+
     /// Create a new terminal session
+    ///
+    /// The creating client becomes the owner and is attached immediately.
+    /// Without a profile, a local shell is started in the workspace directory.
+    /// In tmux mode local terminals attach to the owner's tmux session named
+    /// `tmux_session` (default: the terminal id), creating it if needed.
     pub async fn create_terminal(
         &self,
         session_id: &str,
+        client: &Client,
         cols: u16,
         rows: u16,
-        output_callback: Box<dyn Fn(String) + Send + 'static>,
+        profile: Option<&Profile>,
+        tmux_session: Option<&str>,
     ) -> Result<TerminalHandle, TerminalError> {
         // Check max terminals
         {
             let sessions = self.sessions.read().await;
-            if sessions.len() >= self.max_terminals {
+            if sessions.len() >= self.app_config.get().max_terminals {
                 return Err(TerminalError::MaxTerminalsReached);
             }
         }
 
-        // Use workspace directory as working directory
-        let cwd = self.app_config.workspace_dir.clone();
+        let env: Vec<(String, String)> = profile
+            .map(|p| p.env.clone().into_iter().collect())
+            .unwrap_or_default();
+        let command = profile.and_then(|p| p.command.clone());
 
-        // Create directory if it doesn't exist
-        if let Err(e) = std::fs::create_dir_all(&cwd) {
-            tracing::warn!("Failed to create workspace directory {}: {}", cwd, e);
-        }
+        let clients: Clients = Arc::new(Mutex::new(vec![client.clone()]));
+        let scrollback = Arc::new(Mutex::new(Scrollback::default()));
+        let byte_callback = fan_out(session_id, &clients, &scrollback);
+        let now = Utc::now();
+
+        let backend = match profile.map(|p| p.backend) {
+            Some(ProfileBackend::Ssh) => Backend::Ssh,
+            _ => Backend::Local,
+        };
+        let tmux_session = (backend == Backend::Local && self.app_config.get().tmux)
+            .then(|| tmux_session.unwrap_or(session_id).to_string());
+        let cgroup = (backend == Backend::Local)
+            .then(|| CgroupSpec::for_terminal(&self.app_config.get(), &client.username, session_id))
+            .flatten();
+        let sandbox = (backend == Backend::Local)
+            .then(|| SandboxSpec::from_config(&self.app_config.get()))
+            .flatten();
+        let handle = match (backend, profile) {
+            (Backend::Ssh, Some(profile)) => {
+                let config = SshConfig {
+                    host: profile.host.clone().unwrap_or_default(),
+                    port: profile.port,
+                    user: profile.user.clone().unwrap_or_default(),
+                    auth: SshAuth::from_method(&profile.auth).ok_or_else(|| {
+                        TerminalError::SshError(format!(
+                            "Profile {} has no SSH credentials",
+                            profile.name
+                        ))
+                    })?,
+                };
+                let options = ShellOptions {
+                    cols,
+                    rows,
+                    command,
+                    cwd: profile.cwd.clone(),
+                    env,
+                };
+                self.pty_manager
+                    .spawn_ssh(session_id.to_string(), config, options, byte_callback)
+                    .await?
+            }
+            _ => {
+                // Use the profile's directory, else the workspace directory
+                let cwd = profile
+                    .and_then(|p| p.cwd.clone())
+                    .unwrap_or_else(|| self.app_config.get().workspace_dir.clone());
 
-        let env = vec![];
+                // Create directory if it doesn't exist
+                if let Err(e) = std::fs::create_dir_all(&cwd) {
+                    tracing::warn!("Failed to create workspace directory {}: {}", cwd, e);
+                }
 
-        // Wrap the string callback to work with Vec<u8>
-        let byte_callback = move |data: Vec<u8>| {
-            if let Ok(s) = String::from_utf8(data) {
-                output_callback(s);
+                let command = match &tmux_session {
+                    Some(name) => Some(tmux::attach_command(
+                        &client.username,
+                        name,
+                        command.as_deref(),
+                    )),
+                    None => command,
+                };
+                let meta = serde_json::to_value(TerminalMeta {
+                    owner: client.username.clone(),
+                    name: None,
+                    tags: Vec::new(),
+                    profile: profile.map(|p| p.name.clone()),
+                    tmux_session: tmux_session.clone(),
+                    created_at: now,
+                    cgroup: cgroup.as_ref().map(|c| c.path.clone()),
+                    sandboxed: sandbox.is_some(),
+                })
+                .unwrap_or_default();
+                self.pty_manager
+                    .spawn(
+                        session_id.to_string(),
+                        cols,
+                        rows,
+                        command,
+                        Some(cwd),
+                        env,
+                        meta,
+                        cgroup.clone(),
+                        sandbox.clone(),
+                        byte_callback,
+                    )
+                    .await?
             }
         };
 
-        let handle = self
-            .pty_manager
-            .spawn(
-                session_id.to_string(),
-                cols,
-                rows,
-                Some(cwd),
-                env,
-                byte_callback,
-            )
-            .await?;
-
         let session = SessionState {
             handle: handle.clone(),
-            last_activity: Utc::now(),
-            connected: true,
+            owner: client.username.clone(),
+            name: None,
+            tags: Vec::new(),
+            backend,
+            profile: profile.map(|p| p.name.clone()),
+            tmux_session,
+            cols,
+            rows,
+            created_at: now,
+            last_activity: now,
+            shared_with: HashSet::new(),
+            shared_links: HashMap::new(),
+            clients,
+            scrollback,
+            controller: Some(client.connection_id.clone()),
+            status: ProcessStatus::default(),
+            usage: None,
+            over_limits: Vec::new(),
+            oom_kills: cgroup
+                .as_ref()
+                .and_then(|c| cgroup::oom_kills(&c.path))
+                .unwrap_or(0),
+            cgroup: cgroup.map(|c| c.path),
+            sandboxed: sandbox.is_some(),
         };
+        session.send_role(session_id, &client.connection_id);
+        session.broadcast_participants(session_id);
 
         // Store session
         self.sessions
@@ -150,15 +842,346 @@ impl SessionManager {
         Ok(handle)
     }
 
+    /// Take back terminals left running under the supervisor
+    ///
+    /// Called once at startup. Restored terminals have no clients until
+    /// their owner (or someone they are shared with again) attaches.
+    pub async fn restore(&self, supervisor: &SupervisorClient) -> Result<usize, TerminalError> {
+        let snapshots = supervisor.list().await?;
+        let mut restored = 0;
+
+        for snapshot in snapshots {
+            let meta: TerminalMeta = match serde_json::from_value(snapshot.meta) {
+                Ok(meta) => meta,
+                Err(e) => {
+                    tracing::warn!("Skipping supervised terminal {}: {}", snapshot.id, e);
+                    continue;
+                }
+            };
+
+            let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+            let mut history = Scrollback::default();
+            history.push(&snapshot.scrollback);
+            let scrollback = Arc::new(Mutex::new(history));
+            let byte_callback = fan_out(&snapshot.id, &clients, &scrollback);
+
+            let handle = match self
+                .pty_manager
+                .adopt(snapshot.id.clone(), snapshot.pid, byte_callback)
+                .await
+            {
+                Ok(handle) => handle,
+                Err(e) => {
+                    tracing::info!("Not restoring terminal {}: {}", snapshot.id, e);
+                    continue;
+                }
+            };
+            tracing::info!(
+                "Restored terminal {} owned by {} (pid {:?})",
+                snapshot.id,
+                meta.owner,
+                snapshot.pid
+            );
+
+            self.sessions.write().await.insert(
+                snapshot.id,
+                SessionState {
+                    handle,
+                    owner: meta.owner,
+                    name: meta.name,
+                    tags: meta.tags,
+                    backend: Backend::Local,
+                    profile: meta.profile,
+                    tmux_session: meta.tmux_session,
+                    cols: snapshot.cols,
+                    rows: snapshot.rows,
+                    created_at: meta.created_at,
+                    last_activity: Utc::now(),
+                    shared_with: HashSet::new(),
+                    shared_links: HashMap::new(),
+                    clients,
+                    scrollback,
+                    controller: None,
+                    status: ProcessStatus::default(),
+                    usage: None,
+                    over_limits: Vec::new(),
+                    oom_kills: meta
+                        .cgroup
+                        .as_deref()
+                        .and_then(cgroup::oom_kills)
+                        .unwrap_or(0),
+                    cgroup: meta.cgroup,
+                    sandboxed: meta.sandboxed,
+                },
+            );
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
+    /// Attach a client to an existing terminal
+    pub async fn attach_terminal(
+        &self,
+        session_id: &str,
+        client: &Client,
+    ) -> Result<(), TerminalError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| TerminalError::NotFound(session_id.to_string()))?;
+
+        // Checked under the write lock, so concurrent guests cannot overrun the limit
+        session.admits(session_id, client)?;
+
+        session.attach(session_id, client);
+
+        tracing::info!(
+            "Client {} ({}) attached to terminal {}",
+            client.connection_id,
+            client.username,
+            session_id
+        );
+        Ok(())
+    }
+
+    /// Attach a client to any terminal as a spectator, bypassing access checks
+    ///
+    /// Used for admin shadowing. Returns the terminal owner.
+    pub async fn shadow_terminal(
+        &self,
+        session_id: &str,
+        client: &Client,
+    ) -> Result<String, TerminalError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| TerminalError::NotFound(session_id.to_string()))?;
+
+        let shadow = Client {
+            read_only: true,
+            ..client.clone()
+        };
+        session.attach(session_id, &shadow);
+        Ok(session.owner.clone())
+    }
+
+    /// Show a notice to every client attached to a terminal
+    pub async fn notify(&self, session_id: &str, message: &str) {
+        let sessions = self.sessions.read().await;
+        if let Some(session) = sessions.get(session_id) {
+            broadcast(
+                &session.clients,
+                WsMessage::TerminalNotice(TerminalNotice {
+                    id: session_id.to_string(),
+                    message: message.to_string(),
+                }),
+            );
+        }
+    }
+
+    /// Detach a client from a terminal
+    pub async fn detach_terminal(&self, session_id: &str, connection_id: &str) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(session) = sessions.get_mut(session_id) {
+            session.remove_clients(session_id, |c| c.connection_id == connection_id);
+        }
+    }
+
+    /// Detach a client from every terminal (on disconnect)
+    pub async fn detach_client(&self, connection_id: &str) {
+        let mut sessions = self.sessions.write().await;
+        for (id, session) in sessions.iter_mut() {
+            if session.is_attached(connection_id) {
+                session.remove_clients(id, |c| c.connection_id == connection_id);
+            }
+        }
+    }
+
+    /// Pass input control to an attached connection (owner only)
+    pub async fn set_controller(
+        &self,
+        session_id: &str,
+        owner: &str,
+        connection_id: &str,
+    ) -> Result<(), TerminalError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .filter(|s| s.owner == owner)
+            .ok_or_else(|| TerminalError::AccessDenied(session_id.to_string()))?;
+
+        let read_only = lock(&session.clients)
+            .iter()
+            .find(|c| c.connection_id == connection_id)
+            .map(|c| c.read_only)
+            .ok_or_else(|| TerminalError::NotFound(connection_id.to_string()))?;
+        if read_only {
+            return Err(TerminalError::ReadOnly(session_id.to_string()));
+        }
+
+        session.set_controller(session_id, Some(connection_id.to_string()));
+        session.broadcast_participants(session_id);
+        tracing::info!(
+            "User {} passed control of terminal {} to {}",
+            owner,
+            session_id,
+            connection_id
+        );
+        Ok(())
+    }
+
+    /// Allow another user to attach to a terminal (owner only)
+    pub async fn grant_access(
+        &self,
+        session_id: &str,
+        owner: &str,
+        username: &str,
+    ) -> Result<(), TerminalError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .filter(|s| s.owner == owner)
+            .ok_or_else(|| TerminalError::AccessDenied(session_id.to_string()))?;
+
+        session.shared_with.insert(username.to_string());
+        tracing::info!(
+            "User {} granted {} access to terminal {}",
+            owner,
+            username,
+            session_id
+        );
+        Ok(())
+    }
+
+    /// Let up to `max_viewers` of a share link's guests attach (owner only)
+    pub async fn grant_link(
+        &self,
+        session_id: &str,
+        owner: &str,
+        share_id: &str,
+        max_viewers: Option<usize>,
+    ) -> Result<(), TerminalError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .filter(|s| s.owner == owner)
+            .ok_or_else(|| TerminalError::AccessDenied(session_id.to_string()))?;
+
+        session
+            .shared_links
+            .insert(share_id.to_string(), max_viewers);
+        Ok(())
+    }
+
+    /// Withdraw a share link's access and detach its guests (owner only)
+    pub async fn revoke_link(
+        &self,
+        session_id: &str,
+        owner: &str,
+        share_id: &str,
+    ) -> Result<(), TerminalError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .filter(|s| s.owner == owner)
+            .ok_or_else(|| TerminalError::AccessDenied(session_id.to_string()))?;
+
+        session.shared_links.remove(share_id);
+        session.remove_clients(session_id, |c| c.share_id.as_deref() == Some(share_id));
+        Ok(())
+    }
+
+    /// Set a terminal's name and tags (owner only)
+    ///
+    /// Fields left out are kept. An empty name clears it. Attached clients
+    /// are sent the result.
+    pub async fn update_terminal(
+        &self,
+        session_id: &str,
+        owner: &str,
+        name: Option<String>,
+        tags: Option<Vec<String>>,
+    ) -> Result<(), TerminalError> {
+        let name = name.map(|n| n.trim().to_string());
+        if let Some(name) = &name {
+            if name.chars().count() > MAX_NAME_LEN {
+                return Err(TerminalError::InvalidRequest(format!(
+                    "Name is longer than {} characters",
+                    MAX_NAME_LEN
+                )));
+            }
+        }
+        let tags = tags.map(normalize_tags).transpose()?;
+
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .filter(|s| s.owner == owner)
+            .ok_or_else(|| TerminalError::AccessDenied(session_id.to_string()))?;
+
+        if let Some(name) = name {
+            session.name = Some(name).filter(|n| !n.is_empty());
+        }
+        if let Some(tags) = tags {
+            session.tags = tags;
+        }
+        // Kept with the supervisor so a restart brings them back
+        if session.handle.persistent {
+            let meta = serde_json::to_value(session.meta()).unwrap_or_default();
+            if let Err(e) = self.pty_manager.update_meta(session_id, meta).await {
+                tracing::warn!("Failed to store details of terminal {}: {}", session_id, e);
+            }
+        }
+        broadcast(
+            &session.clients,
+            WsMessage::TerminalUpdated(TerminalUpdated {
+                id: session_id.to_string(),
+                name: session.name.clone(),
+                tags: session.tags.clone(),
+            }),
+        );
+        Ok(())
+    }
+
+    /// Withdraw a user's access to a terminal and detach them (owner only)
+    pub async fn revoke_access(
+        &self,
+        session_id: &str,
+        owner: &str,
+        username: &str,
+    ) -> Result<(), TerminalError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .filter(|s| s.owner == owner)
+            .ok_or_else(|| TerminalError::AccessDenied(session_id.to_string()))?;
+
+        session.shared_with.remove(username);
+        if username != owner {
+            session.remove_clients(session_id, |c| c.username == username);
+        }
+        tracing::info!(
+            "User {} revoked {} access to terminal {}",
+            owner,
+            username,
+            session_id
+        );
+        Ok(())
+    }
+
     /// Write input to a terminal
     pub async fn write_to_terminal(
         &self,
         session_id: &str,
+        connection_id: &str,
         input: &str,
     ) -> Result<(), TerminalError> {
         let sessions = self.sessions.read().await;
 
         if let Some(session) = sessions.get(session_id) {
+            if !session.is_controller(connection_id) {
+                return Err(TerminalError::ReadOnly(session_id.to_string()));
+            }
             let handle = session.handle.clone();
             drop(sessions);
 
@@ -176,26 +1199,142 @@ impl SessionManager {
         }
     }
 
-    /// Resize a terminal
+    /// Resize a terminal (controller only) and notify every attached client
     pub async fn resize_terminal(
         &self,
         session_id: &str,
+        connection_id: &str,
         cols: u16,
         rows: u16,
     ) -> Result<(), TerminalError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| TerminalError::NotFound(session_id.to_string()))?;
+
+        if !session.is_controller(connection_id) {
+            return Err(TerminalError::ReadOnly(session_id.to_string()));
+        }
+
         self.pty_manager.resize(session_id, cols, rows).await?;
-        self.touch(session_id).await;
+        session.cols = cols;
+        session.rows = rows;
+        session.last_activity = Utc::now();
+
+        broadcast(
+            &session.clients,
+            WsMessage::TerminalResized(TerminalResize {
+                id: session_id.to_string(),
+                cols,
+                rows,
+            }),
+        );
         Ok(())
     }
 
-    /// Close a terminal
-    pub async fn close_terminal(&self, session_id: &str) {
-        if let Err(e) = self.pty_manager.close(session_id).await {
-            tracing::warn!("Error closing terminal {}: {}", session_id, e);
+    /// Send a signal to a terminal's processes (controller only)
+    pub async fn signal_terminal(
+        &self,
+        session_id: &str,
+        connection_id: &str,
+        signal: Signal,
+        target: SignalTarget,
+    ) -> Result<(), TerminalError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| TerminalError::NotFound(session_id.to_string()))?;
+
+        if !session.is_controller(connection_id) {
+            return Err(TerminalError::ReadOnly(session_id.to_string()));
         }
 
+        self.pty_manager.signal(session_id, signal, target).await?;
+        session.last_activity = Utc::now();
+        Ok(())
+    }
+
+    /// Close a terminal
+    pub async fn close_terminal(&self, session_id: &str) -> Result<(), TerminalError> {
+        let result = self.pty_manager.close(session_id).await;
+
         let mut sessions = self.sessions.write().await;
-        sessions.remove(session_id);
+        if let Some(session) = sessions.remove(session_id) {
+            broadcast(
+                &session.clients,
+                WsMessage::ShellExit(ShellExit {
+                    id: session_id.to_string(),
+                    code: None,
+                }),
+            );
+        }
+
+        result
+    }
+
+    /// Close every terminal that would not survive a restart (server shutdown)
+    pub async fn close_all(&self) {
+        let ids: Vec<String> = self
+            .sessions
+            .read()
+            .await
+            .iter()
+            .filter(|(_, session)| !session.handle.persistent)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in ids {
+            if let Err(e) = self.close_terminal(&id).await {
+                tracing::warn!("Error closing terminal {}: {}", id, e);
+            }
+        }
+        self.pty_manager.wait_closed().await;
+    }
+
+    /// Number of open terminals that would not survive a restart
+    pub async fn terminal_count(&self) -> usize {
+        let sessions = self.sessions.read().await;
+        sessions.values().filter(|s| !s.handle.persistent).count()
+    }
+
+    /// Check if a user owns a terminal
+    pub async fn is_owner(&self, session_id: &str, username: &str) -> bool {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .is_some_and(|session| session.owner == username)
+    }
+
+    /// Host profile a terminal was opened with
+    pub async fn terminal_profile(&self, session_id: &str) -> Option<String> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .and_then(|session| session.profile.clone())
+    }
+
+    /// List all terminals
+    pub async fn list_terminals(&self) -> Vec<TerminalInfo> {
+        let now = Utc::now();
+        let sessions = self.sessions.read().await;
+        let mut list: Vec<TerminalInfo> = sessions
+            .iter()
+            .map(|(id, session)| session.info(id, now))
+            .collect();
+        list.sort_by_key(|t| t.created_at);
+        list
+    }
+
+    /// Terminals a user owns or has been granted access to
+    pub async fn terminals_for(&self, username: &str) -> Vec<TerminalInfo> {
+        let now = Utc::now();
+        let sessions = self.sessions.read().await;
+        let mut list: Vec<TerminalInfo> = sessions
+            .iter()
+            .filter(|(_, session)| session.can_attach(username))
+            .map(|(id, session)| session.info(id, now))
+            .collect();
+        list.sort_by_key(|t| t.created_at);
+        list
     }
 
     /// Update activity timestamp
@@ -205,3 +1344,267 @@ impl SessionManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Settings};
+
+    fn manager() -> SessionManager {
+        let config = SharedConfig::new(Config::default(), None, Settings::default());
+        SessionManager::new(config, AuditLog::open(None).unwrap(), None)
+    }
+
+    /// Add a terminal owned by `owner`, returning its input and output ends
+    async fn add_terminal(
+        manager: &SessionManager,
+        id: &str,
+        owner: &str,
+    ) -> (mpsc::Receiver<Vec<u8>>, impl Fn(Vec<u8>)) {
+        let (input_tx, input_rx) = mpsc::channel(16);
+        let clients = Clients::default();
+        let scrollback = Arc::new(Mutex::new(Scrollback::default()));
+        let output = fan_out(id, &clients, &scrollback);
+        let now = Utc::now();
+        let session = SessionState {
+            handle: TerminalHandle {
+                input_tx,
+                pid: None,
+                persistent: false,
+            },
+            owner: owner.to_string(),
+            name: None,
+            tags: Vec::new(),
+            backend: Backend::Local,
+            profile: None,
+            tmux_session: None,
+            cols: 80,
+            rows: 24,
+            created_at: now,
+            last_activity: now,
+            shared_with: HashSet::new(),
+            shared_links: HashMap::new(),
+            clients,
+            scrollback,
+            controller: None,
+            status: ProcessStatus::default(),
+            usage: None,
+            over_limits: Vec::new(),
+            cgroup: None,
+            oom_kills: 0,
+            sandboxed: false,
+        };
+        manager
+            .sessions
+            .write()
+            .await
+            .insert(id.to_string(), session);
+        (input_rx, output)
+    }
+
+    fn client(
+        connection_id: &str,
+        username: &str,
+        read_only: bool,
+    ) -> (Client, mpsc::UnboundedReceiver<Envelope>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let client = Client {
+            connection_id: connection_id.to_string(),
+            username: username.to_string(),
+            tx,
+            read_only,
+            hidden: false,
+            share_id: None,
+        };
+        (client, rx)
+    }
+
+    /// Terminal output a client has received so far
+    fn received_output(rx: &mut mpsc::UnboundedReceiver<Envelope>) -> String {
+        let mut output = String::new();
+        while let Ok(envelope) = rx.try_recv() {
+            if let WsMessage::ShellOutput(shell_output) = envelope.msg {
+                output.push_str(&shell_output.output);
+            }
+        }
+        output
+    }
+
+    #[tokio::test]
+    async fn test_output_reaches_every_client() {
+        let manager = manager();
+        let (_input, output) = add_terminal(&manager, "t1", "alice").await;
+        manager.grant_access("t1", "alice", "bob").await.unwrap();
+
+        let (alice, mut alice_rx) = client("c1", "alice", false);
+        let (bob, mut bob_rx) = client("c2", "bob", false);
+        manager.attach_terminal("t1", &alice).await.unwrap();
+        manager.attach_terminal("t1", &bob).await.unwrap();
+        let (mallory, _) = client("c3", "mallory", false);
+        assert!(matches!(
+            manager.attach_terminal("t1", &mallory).await,
+            Err(TerminalError::AccessDenied(_))
+        ));
+
+        output(b"hello".to_vec());
+        assert_eq!(received_output(&mut alice_rx), "hello");
+        assert_eq!(received_output(&mut bob_rx), "hello");
+
+        // A late joiner catches up from the scrollback
+        let (bob2, mut bob2_rx) = client("c4", "bob", false);
+        manager.attach_terminal("t1", &bob2).await.unwrap();
+        output(b" world".to_vec());
+        assert_eq!(received_output(&mut bob2_rx), "hello world");
+        assert_eq!(received_output(&mut alice_rx), " world");
+        assert_eq!(received_output(&mut bob_rx), " world");
+
+        // Detached clients get nothing more
+        manager.detach_client("c2").await;
+        output(b"!".to_vec());
+        assert_eq!(received_output(&mut bob_rx), "");
+        assert_eq!(received_output(&mut alice_rx), "!");
+    }
+
+    #[tokio::test]
+    async fn test_share_link_access() {
+        let manager = manager();
+        let (_input, output) = add_terminal(&manager, "t1", "alice").await;
+        manager
+            .grant_link("t1", "alice", "link-1", Some(1))
+            .await
+            .unwrap();
+
+        let (mut guest, mut guest_rx) = client("c1", "guest-1234", true);
+        guest.share_id = Some("link-1".to_string());
+        manager.attach_terminal("t1", &guest).await.unwrap();
+
+        // A login under the guest's name is not the guest
+        let (impostor, _) = client("c2", "guest-1234", false);
+        assert!(matches!(
+            manager.attach_terminal("t1", &impostor).await,
+            Err(TerminalError::AccessDenied(_))
+        ));
+        // One viewer at a time; reattaching the same connection is fine
+        manager.attach_terminal("t1", &guest).await.unwrap();
+        let (mut second, _) = client("c4", "guest-1234", true);
+        second.share_id = Some("link-1".to_string());
+        assert!(matches!(
+            manager.attach_terminal("t1", &second).await,
+            Err(TerminalError::ViewerLimit(_))
+        ));
+
+        let (mut other, _) = client("c3", "guest-5678", true);
+        other.share_id = Some("link-2".to_string());
+        assert!(matches!(
+            manager.attach_terminal("t1", &other).await,
+            Err(TerminalError::AccessDenied(_))
+        ));
+
+        manager.revoke_link("t1", "alice", "link-1").await.unwrap();
+        output(b"secret".to_vec());
+        assert_eq!(received_output(&mut guest_rx), "");
+        assert!(matches!(
+            manager.attach_terminal("t1", &guest).await,
+            Err(TerminalError::AccessDenied(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_spectator_input_rejected() {
+        let manager = manager();
+        let (mut input, _output) = add_terminal(&manager, "t1", "alice").await;
+        manager.grant_access("t1", "alice", "bob").await.unwrap();
+
+        let (alice, _alice_rx) = client("c1", "alice", false);
+        let (bob, _bob_rx) = client("c2", "bob", false);
+        let (guest, _guest_rx) = client("c3", "guest", true);
+        manager.attach_terminal("t1", &alice).await.unwrap();
+        manager.attach_terminal("t1", &bob).await.unwrap();
+        manager.shadow_terminal("t1", &guest).await.unwrap();
+
+        // The owner controls; spectators may not type
+        manager.write_to_terminal("t1", "c1", "ls\n").await.unwrap();
+        assert_eq!(input.recv().await.unwrap(), b"ls\n");
+        for spectator in ["c2", "c3"] {
+            assert!(matches!(
+                manager
+                    .write_to_terminal("t1", spectator, "rm -rf /\n")
+                    .await,
+                Err(TerminalError::ReadOnly(_))
+            ));
+        }
+        assert!(input.try_recv().is_err());
+
+        // Control passes to bob, but never to a read-only client
+        assert!(matches!(
+            manager.set_controller("t1", "alice", "c3").await,
+            Err(TerminalError::ReadOnly(_))
+        ));
+        assert!(matches!(
+            manager.set_controller("t1", "bob", "c2").await,
+            Err(TerminalError::AccessDenied(_))
+        ));
+        manager.set_controller("t1", "alice", "c2").await.unwrap();
+        assert!(matches!(
+            manager.write_to_terminal("t1", "c1", "ls\n").await,
+            Err(TerminalError::ReadOnly(_))
+        ));
+        manager
+            .write_to_terminal("t1", "c2", "pwd\n")
+            .await
+            .unwrap();
+        assert_eq!(input.recv().await.unwrap(), b"pwd\n");
+    }
+
+    #[tokio::test]
+    async fn test_control_returns_to_owner() {
+        let manager = manager();
+        let (_input, _output) = add_terminal(&manager, "t1", "alice").await;
+        manager.grant_access("t1", "alice", "bob").await.unwrap();
+
+        let (alice, _alice_rx) = client("c1", "alice", false);
+        let (bob, mut bob_rx) = client("c2", "bob", false);
+        manager.attach_terminal("t1", &alice).await.unwrap();
+        manager.attach_terminal("t1", &bob).await.unwrap();
+        manager.set_controller("t1", "alice", "c2").await.unwrap();
+
+        let role = |rx: &mut mpsc::UnboundedReceiver<Envelope>| {
+            let mut role = None;
+            while let Ok(envelope) = rx.try_recv() {
+                if let WsMessage::TerminalRole(msg) = envelope.msg {
+                    role = Some(msg.role);
+                }
+            }
+            role
+        };
+        assert_eq!(role(&mut bob_rx), Some(ParticipantRole::Controller));
+
+        // The controller leaving hands control back to the owner
+        manager.detach_terminal("t1", "c2").await;
+        manager.write_to_terminal("t1", "c1", "ls\n").await.unwrap();
+
+        // With the owner gone too, nobody controls until the owner returns
+        manager.attach_terminal("t1", &bob).await.unwrap();
+        manager.detach_client("c1").await;
+        assert!(matches!(
+            manager.write_to_terminal("t1", "c2", "ls\n").await,
+            Err(TerminalError::ReadOnly(_))
+        ));
+        let (alice2, mut alice2_rx) = client("c4", "alice", false);
+        manager.attach_terminal("t1", &alice2).await.unwrap();
+        assert_eq!(role(&mut alice2_rx), Some(ParticipantRole::Controller));
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        let tags = |t: &[&str]| t.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            normalize_tags(tags(&[" prod ", "", "db", "prod"])).unwrap(),
+            tags(&["prod", "db"])
+        );
+        assert!(normalize_tags(tags(&[&"x".repeat(MAX_TAG_LEN + 1)])).is_err());
+        let many: Vec<String> = (0..=MAX_TAGS).map(|i| i.to_string()).collect();
+        assert!(normalize_tags(many).is_err());
+    }
+}
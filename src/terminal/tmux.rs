@@ -0,0 +1,142 @@
+//! tmux-backed terminals
+//!
+//! In tmux mode a local terminal runs `tmux new-session -A` instead of a bare
+//! login shell, so closing the browser only detaches and the user can come
+//! back to the same session later. Session names carry the (encoded) owner,
+//! keeping each user's sessions apart on the shared tmux server.
+
+use tokio::process::Command;
+
+use crate::ssh::shell_quote;
+use crate::types::TmuxSession;
+
+/// Prefix of every tmux session webshell creates
+const PREFIX: &str = "webshell-";
+
+/// Encode a name into tmux-safe characters (letters and digits, `_xx` escapes)
+fn encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    encoded
+}
+
+/// Reverse of [`encode`]
+fn decode(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut chars = encoded.bytes();
+    while let Some(byte) = chars.next() {
+        if byte == b'_' {
+            let hex = [chars.next()?, chars.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// tmux session name for a user's session
+pub fn session_name(username: &str, name: &str) -> String {
+    format!("{}{}-{}", PREFIX, encode(username), encode(name))
+}
+
+/// Shell command creating or attaching to a user's session
+///
+/// A `command` only runs when the session is created.
+pub fn attach_command(username: &str, name: &str, command: Option<&str>) -> String {
+    let mut line = format!(
+        "exec tmux new-session -A -s {}",
+        session_name(username, name)
+    );
+    if let Some(command) = command {
+        line.push(' ');
+        line.push_str(&shell_quote(command));
+    }
+    line
+}
+
+/// A user's tmux sessions, oldest first
+///
+/// Empty if tmux is not running (or not installed).
+pub async fn list_sessions(username: &str) -> Vec<TmuxSession> {
+    let output = Command::new("tmux")
+        .args([
+            "list-sessions",
+            "-F",
+            "#{session_name}\t#{session_created}\t#{session_windows}\t#{session_attached}",
+        ])
+        .output()
+        .await;
+
+    match output {
+        Ok(output) if output.status.success() => {
+            parse_sessions(username, &String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            tracing::warn!("Failed to run tmux: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Pick a user's sessions out of `tmux list-sessions` output
+fn parse_sessions(username: &str, output: &str) -> Vec<TmuxSession> {
+    let prefix = format!("{}{}-", PREFIX, encode(username));
+    let mut sessions: Vec<TmuxSession> = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = decode(fields.next()?.strip_prefix(&prefix)?)?;
+            Some(TmuxSession {
+                name,
+                created: fields.next()?.parse().ok()?,
+                windows: fields.next()?.parse().ok()?,
+                attached: fields.next()?.parse().ok()?,
+            })
+        })
+        .collect();
+    sessions.sort_by_key(|s| s.created);
+    sessions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_names() {
+        assert_eq!(session_name("alice", "main"), "webshell-alice-main");
+        // No separator or tmux-reserved character survives encoding
+        assert_eq!(session_name("a.b", "x-y:z"), "webshell-a_2eb-x_2dy_3az");
+        assert_ne!(session_name("a.b", "c"), session_name("a_b", "c"));
+        assert_eq!(
+            decode(&encode("dev box ünï")).as_deref(),
+            Some("dev box ünï")
+        );
+
+        assert_eq!(
+            attach_command("bob", "logs", Some("tail -f 'x'")),
+            "exec tmux new-session -A -s webshell-bob-logs 'tail -f '\\''x'\\'''"
+        );
+    }
+
+    #[test]
+    fn test_parse_sessions() {
+        let output = "webshell-bob-b\t200\t1\t0\n\
+                      webshell-alice-main\t300\t2\t1\n\
+                      webshell-bob-a_2e1\t100\t3\t1\n\
+                      other\t50\t1\t0\n";
+        let sessions = parse_sessions("bob", output);
+        let names: Vec<&str> = sessions.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["a.1", "b"]);
+        assert_eq!(sessions[0].windows, 3);
+        assert_eq!(sessions[0].attached, 1);
+    }
+}
@@ -0,0 +1,231 @@
+//! Per-terminal resource usage
+//!
+//! Sums CPU time, resident memory, process count and storage I/O over the
+//! process tree under each local shell, read from `/proc`. Processes that
+//! detach from the tree (e.g. daemons re-parented to init) are not counted.
+
+use nix::unistd::{sysconf, SysconfVar};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::config::Config;
+
+/// Resource usage of a terminal's process tree
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// CPU time used, including reaped children (seconds)
+    pub cpu_secs: f64,
+    /// CPU use since the previous sample (100 = one core)
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+    pub processes: u32,
+    /// Bytes read from storage
+    pub read_bytes: u64,
+    /// Bytes written to storage
+    pub write_bytes: u64,
+}
+
+/// A limit a terminal went over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Cpu,
+    Memory,
+    Processes,
+}
+
+impl Limit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Limit::Cpu => "cpu_percent",
+            Limit::Memory => "rss_mb",
+            Limit::Processes => "processes",
+        }
+    }
+}
+
+impl ResourceUsage {
+    /// Configured limits this usage is over
+    pub fn exceeded(&self, config: &Config) -> Vec<Limit> {
+        let mut over = Vec::new();
+        if config
+            .usage_cpu_percent
+            .is_some_and(|max| self.cpu_percent > max)
+        {
+            over.push(Limit::Cpu);
+        }
+        if config
+            .usage_rss_mb
+            .is_some_and(|max| self.rss_bytes > max * 1024 * 1024)
+        {
+            over.push(Limit::Memory);
+        }
+        if config
+            .usage_processes
+            .is_some_and(|max| self.processes > max)
+        {
+            over.push(Limit::Processes);
+        }
+        over
+    }
+}
+
+/// One process as read from `/proc/<pid>/stat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Process {
+    pid: u32,
+    ppid: u32,
+    /// utime + stime + cutime + cstime
+    cpu_ticks: u64,
+    rss_pages: u64,
+}
+
+/// Snapshot of every process on the system
+pub struct ProcessTable {
+    processes: HashMap<u32, Process>,
+    children: HashMap<u32, Vec<u32>>,
+    ticks_per_sec: f64,
+    page_size: u64,
+}
+
+impl ProcessTable {
+    /// Read all processes from `/proc`
+    pub fn read() -> Self {
+        let processes = std::fs::read_dir("/proc")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+            .filter_map(|pid| {
+                let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+                parse_stat(pid, &stat)
+            });
+        Self::new(processes)
+    }
+
+    fn new(processes: impl Iterator<Item = Process>) -> Self {
+        let processes: HashMap<u32, Process> = processes.map(|p| (p.pid, p)).collect();
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+        for process in processes.values() {
+            children.entry(process.ppid).or_default().push(process.pid);
+        }
+        let sysconf_or = |var, default| match sysconf(var) {
+            Ok(Some(value)) if value > 0 => value as u64,
+            _ => default,
+        };
+        Self {
+            processes,
+            children,
+            ticks_per_sec: sysconf_or(SysconfVar::CLK_TCK, 100) as f64,
+            page_size: sysconf_or(SysconfVar::PAGE_SIZE, 4096),
+        }
+    }
+
+    /// Usage of `root` and its descendants (`cpu_percent` left at 0)
+    ///
+    /// `None` if `root` is not running.
+    pub fn usage(&self, root: u32) -> Option<ResourceUsage> {
+        self.processes.get(&root)?;
+
+        let mut usage = ResourceUsage::default();
+        let mut ticks = 0;
+        for pid in self.tree(root) {
+            let process = &self.processes[&pid];
+            ticks += process.cpu_ticks;
+            usage.rss_bytes += process.rss_pages * self.page_size;
+            usage.processes += 1;
+            // Only readable for our own processes (or as root)
+            if let Ok(io) = std::fs::read_to_string(format!("/proc/{}/io", pid)) {
+                let (read, write) = parse_io(&io);
+                usage.read_bytes += read;
+                usage.write_bytes += write;
+            }
+        }
+        usage.cpu_secs = ticks as f64 / self.ticks_per_sec;
+        Some(usage)
+    }
+
+    /// `root` and every descendant
+    fn tree(&self, root: u32) -> Vec<u32> {
+        let mut tree = vec![root];
+        let mut next = 0;
+        while next < tree.len() {
+            if let Some(children) = self.children.get(&tree[next]) {
+                tree.extend(children);
+            }
+            next += 1;
+        }
+        tree
+    }
+}
+
+/// Parse `/proc/<pid>/stat`
+///
+/// The command name may contain spaces and parentheses, so fields are
+/// counted from the last `)`.
+fn parse_stat(pid: u32, stat: &str) -> Option<Process> {
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    // Field numbers from proc(5), minus the pid and comm before `)`
+    let field = |n: usize| fields.get(n - 3)?.parse::<u64>().ok();
+    Some(Process {
+        pid,
+        ppid: field(4)? as u32,
+        cpu_ticks: field(14)? + field(15)? + field(16)? + field(17)?,
+        rss_pages: field(24)?,
+    })
+}
+
+/// `read_bytes` and `write_bytes` from `/proc/<pid>/io`
+fn parse_io(io: &str) -> (u64, u64) {
+    let value = |key: &str| {
+        io.lines()
+            .find_map(|line| line.strip_prefix(key)?.trim().parse().ok())
+            .unwrap_or(0)
+    };
+    (value("read_bytes:"), value("write_bytes:"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "4242 (my (odd) cmd) S 4241 4242 4242 34816 4300 4194560 \
+                    100 0 0 0 30 12 5 3 20 0 1 0 12345 8192000 512 \
+                    18446744073709551615 1 1 0 0 0 0 65536 3686404 1266761467 0 0 0 17 3 0 0 0 0 0";
+        let process = parse_stat(4242, stat).unwrap();
+        assert_eq!(process.ppid, 4241);
+        assert_eq!(process.cpu_ticks, 30 + 12 + 5 + 3);
+        assert_eq!(process.rss_pages, 512);
+
+        assert_eq!(
+            parse_io("rchar: 10\nread_bytes: 4096\nwrite_bytes: 8192\n"),
+            (4096, 8192)
+        );
+    }
+
+    #[test]
+    fn test_tree_usage() {
+        let process = |pid, ppid, cpu_ticks| Process {
+            pid,
+            ppid,
+            cpu_ticks,
+            rss_pages: 1,
+        };
+        let table = ProcessTable::new(
+            [
+                process(10, 1, 100),
+                process(11, 10, 50),
+                process(12, 11, 50),
+                process(20, 1, 1000),
+            ]
+            .into_iter(),
+        );
+
+        let usage = table.usage(10).unwrap();
+        assert_eq!(usage.processes, 3);
+        assert_eq!(usage.rss_bytes, 3 * table.page_size);
+        assert_eq!(usage.cpu_secs, 200.0 / table.ticks_per_sec);
+        assert!(table.usage(99).is_none());
+    }
+}
@@ -0,0 +1,282 @@
+//! TLS termination
+//!
+//! Loads a PEM certificate chain and private key into a rustls server config
+//! and swaps in new ones when the files change on disk. Can also generate a
+//! self-signed certificate for quick internal deployments, and optionally
+//! verify client certificates (mTLS).
+
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use thiserror::Error;
+use tokio::time::{interval, Duration};
+use tokio_rustls::TlsAcceptor;
+
+/// How often certificate files are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Failed to read {0}: {1}")]
+    Pem(String, rustls::pki_types::pem::Error),
+
+    #[error("No certificates found in {0}")]
+    NoCertificates(String),
+
+    #[error("TLS error: {0}")]
+    Rustls(#[from] rustls::Error),
+
+    #[error("Failed to write {0}: {1}")]
+    Io(String, std::io::Error),
+
+    #[error("Failed to generate certificate: {0}")]
+    Generate(#[from] rcgen::Error),
+
+    #[error("Invalid client CA: {0}")]
+    ClientCa(#[from] rustls::server::VerifierBuilderError),
+}
+
+/// Verified client certificate of a TLS connection (end-entity, DER)
+#[derive(Debug, Clone)]
+pub struct PeerCertificate(pub CertificateDer<'static>);
+
+/// Client certificate verification settings
+pub struct ClientAuth<'a> {
+    /// CA bundle (PEM) client certificates must chain to
+    pub ca_path: &'a str,
+    /// Reject connections that present no certificate
+    pub required: bool,
+}
+
+/// Serves whichever certificate was loaded last
+#[derive(Debug)]
+struct CertResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        )
+    }
+}
+
+/// Load a certificate chain and private key from PEM files
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey, TlsError> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::Pem(cert_path.to_string(), e))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(cert_path.to_string()));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| TlsError::Pem(key_path.to_string(), e))?;
+    let signing_key = ring::sign::any_supported_type(&key)?;
+
+    // Catch a half-written pair (new certificate, old key) during rotation
+    let certified_key = CertifiedKey::new(certs, signing_key);
+    certified_key.keys_match()?;
+    Ok(certified_key)
+}
+
+/// Build a client certificate verifier from a CA bundle
+fn client_verifier(
+    client_auth: &ClientAuth<'_>,
+) -> Result<Arc<dyn rustls::server::danger::ClientCertVerifier>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(client_auth.ca_path)
+        .map_err(|e| TlsError::Pem(client_auth.ca_path.to_string(), e))?
+    {
+        let cert = cert.map_err(|e| TlsError::Pem(client_auth.ca_path.to_string(), e))?;
+        roots.add(cert)?;
+    }
+    if roots.is_empty() {
+        return Err(TlsError::NoCertificates(client_auth.ca_path.to_string()));
+    }
+
+    let builder = WebPkiClientVerifier::builder_with_provider(
+        Arc::new(roots),
+        Arc::new(ring::default_provider()),
+    );
+    let builder = if client_auth.required {
+        builder
+    } else {
+        // Password login stays available to clients without a certificate
+        builder.allow_unauthenticated()
+    };
+    Ok(builder.build()?)
+}
+
+/// Build a TLS acceptor that reloads its certificate on file change
+pub fn acceptor(
+    cert_path: &str,
+    key_path: &str,
+    client_auth: Option<ClientAuth<'_>>,
+) -> Result<TlsAcceptor, TlsError> {
+    let resolver = Arc::new(CertResolver {
+        current: RwLock::new(Arc::new(load_certified_key(cert_path, key_path)?)),
+    });
+
+    let builder = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_auth {
+        Some(client_auth) => builder.with_client_cert_verifier(client_verifier(&client_auth)?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(resolver.clone());
+    // WebSocket upgrades need HTTP/1.1
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    spawn_reload_task(resolver, cert_path.to_string(), key_path.to_string());
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Modification time of a file, if readable
+fn modified(path: &str) -> Option<SystemTime> {
+    Path::new(path).metadata().and_then(|m| m.modified()).ok()
+}
+
+/// Poll the certificate files and swap in new ones when they change
+fn spawn_reload_task(resolver: Arc<CertResolver>, cert_path: String, key_path: String) {
+    tokio::spawn(async move {
+        let mut last = (modified(&cert_path), modified(&key_path));
+        let mut interval = interval(RELOAD_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let current = (modified(&cert_path), modified(&key_path));
+            if current == last {
+                continue;
+            }
+            last = current;
+
+            match load_certified_key(&cert_path, &key_path) {
+                Ok(key) => {
+                    *resolver.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(key);
+                    tracing::info!("Reloaded TLS certificate from {}", cert_path);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to reload TLS certificate, keeping previous: {}", e);
+                }
+            }
+        }
+    });
+}
+
+/// SHA-256 fingerprint of the first certificate in a PEM file
+pub fn fingerprint(cert_path: &str) -> Result<String, TlsError> {
+    let cert = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|e| TlsError::Pem(cert_path.to_string(), e))?
+        .next()
+        .ok_or_else(|| TlsError::NoCertificates(cert_path.to_string()))?
+        .map_err(|e| TlsError::Pem(cert_path.to_string(), e))?;
+
+    let digest = Sha256::digest(cert.as_ref());
+    Ok(digest
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+/// Return the self-signed certificate in `data_dir`, generating it if missing
+///
+/// Returns the certificate and key paths.
+pub fn ensure_self_signed(data_dir: &str) -> Result<(String, String), TlsError> {
+    let dir = PathBuf::from(data_dir);
+    let cert_path = dir.join("selfsigned-cert.pem");
+    let key_path = dir.join("selfsigned-key.pem");
+    let paths = (
+        cert_path.to_string_lossy().into_owned(),
+        key_path.to_string_lossy().into_owned(),
+    );
+
+    if cert_path.exists() && key_path.exists() {
+        return Ok(paths);
+    }
+
+    let names = subject_alt_names();
+    tracing::info!(
+        "Generating self-signed certificate for: {}",
+        names.join(", ")
+    );
+
+    let key_pair = rcgen::KeyPair::generate()?;
+    let mut params = rcgen::CertificateParams::new(names)?;
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "WebShell self-signed");
+    let cert = params.self_signed(&key_pair)?;
+
+    std::fs::create_dir_all(&dir).map_err(|e| TlsError::Io(data_dir.to_string(), e))?;
+    write_file(&key_path, key_pair.serialize_pem().as_bytes(), 0o600)?;
+    write_file(&cert_path, cert.pem().as_bytes(), 0o644)?;
+
+    Ok(paths)
+}
+
+/// Names the self-signed certificate is valid for
+fn subject_alt_names() -> Vec<String> {
+    let mut names = vec!["localhost".to_string()];
+
+    let hostname = std::env::var("HOSTNAME").ok().or_else(|| {
+        Command::new("hostname")
+            .output()
+            .ok()
+            .and_then(|o| String::from_utf8(o.stdout).ok())
+    });
+    if let Some(hostname) = hostname.map(|h| h.trim().to_string()) {
+        if !hostname.is_empty() && !names.contains(&hostname) {
+            names.push(hostname);
+        }
+    }
+
+    match local_ip_address::list_afinet_netifas() {
+        Ok(interfaces) => {
+            for (_, ip) in interfaces {
+                let ip = ip.to_string();
+                if !names.contains(&ip) {
+                    names.push(ip);
+                }
+            }
+        }
+        Err(e) => tracing::warn!("Failed to list local IP addresses: {}", e),
+    }
+
+    names
+}
+
+/// Write a file with the given Unix permissions
+fn write_file(path: &Path, contents: &[u8], mode: u32) -> Result<(), TlsError> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|e| TlsError::Io(path.display().to_string(), e))
+}
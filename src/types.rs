@@ -2,12 +2,64 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::auth::Role;
+use crate::terminal::session::TerminalInfo;
+
+/// Client greeting, the first message on every connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    /// Highest protocol version the client speaks
+    pub version: u32,
+    /// Optional features the client supports (see [`crate::protocol`])
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+/// Limits the server enforces
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerLimits {
+    pub max_terminals: usize,
+    /// Seconds before an unattached terminal is closed
+    pub idle_timeout: u64,
+    /// Output replayed when attaching (bytes)
+    pub scrollback_bytes: usize,
+}
+
+/// Who the connection is authenticated as
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerUser {
+    pub username: String,
+    pub role: Role,
+    /// Joined through a share link
+    pub guest: bool,
+}
+
+/// Server reply to [`Hello`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerHello {
+    /// Protocol version used on this connection
+    pub version: u32,
+    /// Server name and version
+    pub server: String,
+    /// Features both sides support
+    pub capabilities: Vec<String>,
+    pub limits: ServerLimits,
+    pub user: ServerUser,
+    pub connection_id: String,
+}
+
 /// Terminal open request
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TerminalOpenRequest {
     pub id: String,
     pub cols: u16,
     pub rows: u16,
+    /// Host profile to open (default: local shell)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// tmux session to create or attach (tmux mode only; default: the terminal id)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
 }
 
 /// Terminal input data
@@ -31,6 +83,244 @@ pub struct TerminalClose {
     pub id: String,
 }
 
+/// Signals a client may send to a terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Signal {
+    #[serde(rename = "SIGINT")]
+    Int,
+    #[serde(rename = "SIGTERM")]
+    Term,
+    #[serde(rename = "SIGHUP")]
+    Hup,
+    #[serde(rename = "SIGKILL")]
+    Kill,
+    #[serde(rename = "SIGTSTP")]
+    Tstp,
+    #[serde(rename = "SIGCONT")]
+    Cont,
+}
+
+/// Which processes a signal is sent to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalTarget {
+    /// The PTY's foreground process group (what Ctrl-C would reach)
+    #[default]
+    Foreground,
+    /// The shell itself
+    Leader,
+}
+
+/// Terminal signal request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalSignal {
+    pub id: String,
+    pub signal: Signal,
+    #[serde(default)]
+    pub target: SignalTarget,
+}
+
+/// Terminal attach/detach request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalAttach {
+    pub id: String,
+}
+
+/// Grant or revoke another user's access to a terminal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalAccess {
+    pub id: String,
+    pub username: String,
+}
+
+/// Role of a client attached to a terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParticipantRole {
+    /// Sends input and sets the terminal size
+    Controller,
+    /// Watches output only
+    Spectator,
+}
+
+/// A client attached to a terminal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Participant {
+    pub connection_id: String,
+    pub username: String,
+    pub role: ParticipantRole,
+}
+
+/// Pass input control to another participant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalControl {
+    pub id: String,
+    pub connection_id: String,
+}
+
+/// A client's own role on a terminal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalRole {
+    pub id: String,
+    pub role: ParticipantRole,
+}
+
+/// Clients currently attached to a terminal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalParticipants {
+    pub id: String,
+    pub owner: String,
+    pub participants: Vec<Participant>,
+}
+
+/// Admin request to silently watch a terminal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalShadow {
+    pub id: String,
+    /// Tell the terminal's users they are being watched
+    #[serde(default)]
+    pub notify: bool,
+}
+
+/// Informational notice shown in a terminal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalNotice {
+    pub id: String,
+    pub message: String,
+}
+
+/// Server is shutting down; terminals close after the grace period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerShutdown {
+    pub grace_secs: u64,
+}
+
+/// One of the user's tmux sessions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TmuxSession {
+    /// Name as the user chose it
+    pub name: String,
+    /// Creation time (Unix seconds)
+    pub created: u64,
+    /// Number of windows
+    pub windows: u32,
+    /// Clients attached to it right now
+    pub attached: u32,
+}
+
+/// The user's tmux sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TmuxSessions {
+    pub sessions: Vec<TmuxSession>,
+}
+
+/// A terminal was opened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalOpened {
+    pub id: String,
+    /// Process id of the shell, if known
+    pub pid: Option<u32>,
+    /// Survives a server restart
+    pub persistent: bool,
+}
+
+/// Terminals the client owns or has been granted access to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalList {
+    pub terminals: Vec<TerminalInfo>,
+}
+
+/// Set a terminal's name and tags; omitted fields are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalUpdate {
+    pub id: String,
+    /// New name; empty clears it
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Replacement tags
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+}
+
+/// A terminal's name or tags changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalUpdated {
+    pub id: String,
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Process in the foreground of a terminal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForegroundProcess {
+    pub pid: u32,
+    pub name: String,
+    /// Arguments joined with spaces
+    pub cmdline: String,
+}
+
+/// What a terminal is running right now (local terminals only)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessStatus {
+    /// Leader of the foreground process group
+    pub process: Option<ForegroundProcess>,
+    /// Something other than the shell is in the foreground
+    pub busy: bool,
+    /// Working directory of the foreground process
+    pub cwd: Option<String>,
+}
+
+/// A terminal's foreground process or directory changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalStatus {
+    pub id: String,
+    #[serde(flatten)]
+    pub status: ProcessStatus,
+}
+
+/// Why a request failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Malformed or unexpected message
+    BadRequest,
+    NotFound,
+    AlreadyExists,
+    AccessDenied,
+    /// The client does not hold input control
+    ReadOnly,
+    MaxTerminals,
+    UnknownProfile,
+    /// The shell could not be started or written to
+    SpawnFailed,
+    /// The SSH connection failed
+    SshFailed,
+    /// The supervisor could not be reached
+    SupervisorFailed,
+    Internal,
+}
+
+/// Error reply to a client request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestError {
+    /// Terminal the request was about, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub code: ErrorCode,
+    /// Human-readable description
+    pub message: String,
+}
+
+impl RequestError {
+    pub fn new(id: Option<&str>, code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            id: id.map(str::to_string),
+            code,
+            message: message.into(),
+        }
+    }
+}
+
 /// Shell output from backend
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShellOutput {
@@ -49,6 +339,14 @@ pub struct ShellExit {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum WsMessage {
+    /// Client greeting; must be the first message
+    #[serde(rename = "hello")]
+    Hello(Hello),
+
+    /// Server reply to the greeting
+    #[serde(rename = "server.hello")]
+    ServerHello(ServerHello),
+
     /// Client requests to open a terminal
     #[serde(rename = "term.open")]
     TerminalOpen(TerminalOpenRequest),
@@ -65,6 +363,50 @@ pub enum WsMessage {
     #[serde(rename = "term.close")]
     TerminalClose(TerminalClose),
 
+    /// Client sends a signal to the terminal's processes
+    #[serde(rename = "term.signal")]
+    TerminalSignal(TerminalSignal),
+
+    /// Client attaches to an existing (owned or shared) terminal
+    #[serde(rename = "term.attach")]
+    TerminalAttach(TerminalAttach),
+
+    /// Client detaches from a terminal without closing it
+    #[serde(rename = "term.detach")]
+    TerminalDetach(TerminalAttach),
+
+    /// Owner grants a user access to a terminal
+    #[serde(rename = "term.grant")]
+    TerminalGrant(TerminalAccess),
+
+    /// Owner revokes a user's access to a terminal
+    #[serde(rename = "term.revoke")]
+    TerminalRevoke(TerminalAccess),
+
+    /// Owner passes input control to another participant
+    #[serde(rename = "term.control")]
+    TerminalControl(TerminalControl),
+
+    /// Admin attaches to any terminal as a spectator
+    #[serde(rename = "term.shadow")]
+    TerminalShadow(TerminalShadow),
+
+    /// Server shows an informational notice in a terminal
+    #[serde(rename = "term.notice")]
+    TerminalNotice(TerminalNotice),
+
+    /// Server tells a client its role (controller or spectator)
+    #[serde(rename = "term.role")]
+    TerminalRole(TerminalRole),
+
+    /// Server notifies attached clients of a terminal resize
+    #[serde(rename = "term.resized")]
+    TerminalResized(TerminalResize),
+
+    /// Server sends the list of attached clients
+    #[serde(rename = "term.participants")]
+    TerminalParticipants(TerminalParticipants),
+
     /// Server sends shell output
     #[serde(rename = "shell.output")]
     ShellOutput(ShellOutput),
@@ -72,4 +414,103 @@ pub enum WsMessage {
     /// Server notifies shell exit
     #[serde(rename = "shell.exit")]
     ShellExit(ShellExit),
+
+    /// Client asks for its tmux sessions
+    #[serde(rename = "tmux.list")]
+    TmuxList,
+
+    /// Server sends the client's tmux sessions
+    #[serde(rename = "tmux.sessions")]
+    TmuxSessions(TmuxSessions),
+
+    /// Client asks for the terminals it can attach to
+    #[serde(rename = "term.list")]
+    TerminalList,
+
+    /// Server sends the client's terminals
+    #[serde(rename = "term.terminals")]
+    TerminalTerminals(TerminalList),
+
+    /// Owner sets a terminal's name and tags
+    #[serde(rename = "term.update")]
+    TerminalUpdate(TerminalUpdate),
+
+    /// Server notifies attached clients of a new name or tags
+    #[serde(rename = "term.updated")]
+    TerminalUpdated(TerminalUpdated),
+
+    /// Server reports a terminal's foreground process and directory
+    #[serde(rename = "term.status")]
+    TerminalStatus(TerminalStatus),
+
+    /// Server confirms a terminal was opened
+    #[serde(rename = "term.opened")]
+    TerminalOpened(TerminalOpened),
+
+    /// Server reports a failed request
+    #[serde(rename = "term.error")]
+    TerminalError(RequestError),
+
+    /// Server confirms a request that has no other reply (only if it had a `req_id`)
+    #[serde(rename = "ack")]
+    Ack,
+
+    /// Server announces it is shutting down
+    #[serde(rename = "server.shutdown")]
+    ServerShutdown(ServerShutdown),
+}
+
+/// A message as sent on the wire, with an optional request id
+///
+/// Clients may tag a request with `req_id`; the reply (`term.opened`,
+/// `term.error`, `ack`, ...) carries the same id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    #[serde(flatten)]
+    pub msg: WsMessage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub req_id: Option<String>,
+}
+
+impl From<WsMessage> for Envelope {
+    fn from(msg: WsMessage) -> Self {
+        Self { msg, req_id: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope() {
+        let request: Envelope =
+            serde_json::from_str(r#"{"type": "term.close", "data": {"id": "t1"}, "req_id": "7"}"#)
+                .unwrap();
+        assert!(matches!(request.msg, WsMessage::TerminalClose(ref c) if c.id == "t1"));
+        assert_eq!(request.req_id.as_deref(), Some("7"));
+
+        // Data-less messages, with and without an id
+        let request: Envelope = serde_json::from_str(r#"{"type": "tmux.list"}"#).unwrap();
+        assert!(matches!(request.msg, WsMessage::TmuxList));
+        assert_eq!(request.req_id, None);
+
+        let ack = Envelope {
+            msg: WsMessage::Ack,
+            req_id: Some("7".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_string(&ack).unwrap(),
+            r#"{"type":"ack","req_id":"7"}"#
+        );
+        let error = Envelope::from(WsMessage::TerminalError(RequestError::new(
+            Some("t1"),
+            ErrorCode::MaxTerminals,
+            "Maximum terminals reached",
+        )));
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"type":"term.error","data":{"id":"t1","code":"max_terminals","message":"Maximum terminals reached"}}"#
+        );
+    }
 }